@@ -0,0 +1,233 @@
+use crate::effects;
+use crate::library::{self, Record};
+use crate::scan::ScanOptions;
+use crate::transition;
+use crate::utils;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+// Day to night blending settings, parsed from the [blend] section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlendSettings {
+    // Minutes between checks for a new blend weight.
+    pub interval: i32,
+    // Number of distinct blend weights, each is rendered once and cached.
+    pub buckets: u32,
+    // Solar elevation in degrees above and below the horizon where the blend starts and ends.
+    pub twilight: f64,
+}
+
+impl Default for BlendSettings {
+    fn default() -> Self {
+        BlendSettings {
+            interval: 5,
+            buckets: 20,
+            twilight: 6.0,
+        }
+    }
+}
+
+impl BlendSettings {
+    // Round a weight to its bucket, 0 is full night and `buckets` is full day.
+    pub fn bucket(&self, weight: f64) -> u32 {
+        (weight.clamp(0.0, 1.0) * self.buckets as f64).round() as u32
+    }
+}
+
+// Find images present in both the day and night folder under the same file name.
+pub fn find_pairs(
+    (day_folder, days): (&Path, Vec<Record>),
+    (night_folder, nights): (&Path, Vec<Record>),
+    options: &ScanOptions,
+) -> Vec<(Record, String)> {
+    let relative_images = |folder: &Path, records: Vec<Record>| -> Vec<(PathBuf, Record)> {
+        records
+            .into_iter()
+            .filter_map(|record| {
+                let relative = Path::new(&record.path).strip_prefix(folder).ok()?;
+                let scene = options
+                    .accepts(relative)
+                    .then(|| relative.with_extension(""))?;
+                Some((scene, record))
+            })
+            .collect()
+    };
+    let nights: HashMap<PathBuf, Record> =
+        relative_images(night_folder, nights).into_iter().collect();
+    relative_images(day_folder, days)
+        .into_iter()
+        .filter_map(|(scene, day)| nights.get(&scene).map(|night| (day, night.path.clone())))
+        .collect()
+}
+
+// Pick the least shown scene, by show count of its day image.
+pub fn select_pair(pairs: Vec<(Record, String)>) -> Option<(String, String)> {
+    let days = pairs.iter().map(|(day, _)| day.clone()).collect();
+    let day = library::select(days, 1).pop()?;
+    pairs
+        .into_iter()
+        .find(|(record, _)| record.path == day)
+        .map(|(record, night)| (record.path, night))
+}
+
+// Render the blend for a bucket, or reuse an earlier render of the same pair and bucket.
+pub fn render_cached(
+    (day, night): (&str, &str),
+    bucket: u32,
+    settings: &BlendSettings,
+    size: Option<(u32, u32)>,
+    cache_dir: &Path,
+) -> Result<String> {
+    let size_name = size.map_or("native".to_string(), |(width, height)| {
+        format!("{}x{}", width, height)
+    });
+    let file = cache_dir.join(format!(
+        "{}-{}-{}-{}.jpg",
+        scene_key((day, night)),
+        size_name,
+        bucket,
+        settings.buckets
+    ));
+    if !file.is_file() {
+        std::fs::create_dir_all(cache_dir)?;
+        let size = match size {
+            Some(size) => size,
+            None => image::image_dimensions(day)?,
+        };
+        let night_image = transition::load_fitted(Path::new(night), size)?;
+        let day_image = transition::load_fitted(Path::new(day), size)?;
+        let weight = bucket as f32 / settings.buckets.max(1) as f32;
+        transition::blend(&night_image, &day_image, weight).save(&file)?;
+    }
+    Ok(file.display().to_string())
+}
+
+// Renders of a scene share this file name prefix, it changes when either image is modified.
+fn scene_key((day, night): (&str, &str)) -> String {
+    let mut hasher = DefaultHasher::new();
+    for image in [day, night] {
+        image.hash(&mut hasher);
+        utils::modified_secs(Path::new(image)).hash(&mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+// Remove renders of other scenes, those of the current scene are reused as the sun moves.
+pub fn prune(cache_dir: &Path, scene: Option<(&str, &str)>) {
+    let keep: Vec<String> = match (scene, std::fs::read_dir(cache_dir)) {
+        (Some(scene), Ok(entries)) => {
+            let prefix = format!("{}-", scene_key(scene));
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.file_name()
+                        .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+                })
+                .map(|path| path.display().to_string())
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+    effects::prune(cache_dir, &keep);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::Library;
+    use image::{Rgb, RgbImage};
+    use std::fs;
+
+    #[test]
+    fn test_pairs_and_cached_render() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("day")).unwrap();
+        fs::create_dir_all(root.join("night")).unwrap();
+        RgbImage::from_pixel(4, 4, Rgb([200, 200, 200]))
+            .save(root.join("day/lake.png"))
+            .unwrap();
+        RgbImage::from_pixel(4, 4, Rgb([0, 0, 100]))
+            .save(root.join("night/lake.jpg"))
+            .unwrap();
+        RgbImage::new(4, 4)
+            .save(root.join("day/unpaired.png"))
+            .unwrap();
+
+        let library = Library::open_in_memory().unwrap();
+        library.rescan(root, &ScanOptions::default()).unwrap();
+        let (day_folder, night_folder) = (root.join("day"), root.join("night"));
+        let pairs = find_pairs(
+            (&day_folder, library.images_under(&day_folder).unwrap()),
+            (&night_folder, library.images_under(&night_folder).unwrap()),
+            &ScanOptions::default(),
+        );
+        let (day, night) = select_pair(pairs).unwrap();
+        assert!(day.ends_with("day/lake.png") && night.ends_with("night/lake.jpg"));
+
+        let settings = BlendSettings {
+            buckets: 4,
+            ..Default::default()
+        };
+        let cache_dir = root.join("cache");
+        let render =
+            || render_cached((&day, &night), 2, &settings, Some((2, 2)), &cache_dir).unwrap();
+        let file = render();
+        let pixel = *image::open(&file).unwrap().to_rgb8().get_pixel(0, 0);
+        // Halfway between night and day, allowing for jpeg compression.
+        assert!(
+            pixel
+                .0
+                .iter()
+                .zip([100, 100, 150])
+                .all(|(a, b)| a.abs_diff(b) <= 4)
+        );
+        assert_eq!(render(), file);
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 1);
+
+        // Renders of the current scene survive pruning, other scenes are removed.
+        let other = root.join("day/unpaired.png").display().to_string();
+        render_cached((&other, &night), 1, &settings, None, &cache_dir).unwrap();
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 2);
+        prune(&cache_dir, Some((&day, &night)));
+        assert!(Path::new(&file).is_file());
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 1);
+        prune(&cache_dir, None);
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_pairs_from_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for image in ["day/lake.png", "night/lake.png", "night/forest.png"] {
+            fs::create_dir_all(root.join(image).parent().unwrap()).unwrap();
+            RgbImage::new(2, 2).save(root.join(image)).unwrap();
+        }
+        let options = ScanOptions::default();
+        let scanned = |folder: &Path| {
+            crate::scan::find_images(folder, &options)
+                .into_iter()
+                .map(Record::new)
+                .collect()
+        };
+        let (day_folder, night_folder) = (root.join("day"), root.join("night"));
+        let pairs = find_pairs(
+            (&day_folder, scanned(&day_folder)),
+            (&night_folder, scanned(&night_folder)),
+            &options,
+        );
+        assert_eq!(pairs.len(), 1);
+        assert!(pairs[0].1.ends_with("night/lake.png"));
+    }
+
+    #[test]
+    fn test_bucket() {
+        let settings = BlendSettings::default();
+        assert_eq!(settings.bucket(0.0), 0);
+        assert_eq!(settings.bucket(0.51), 10);
+        assert_eq!(settings.bucket(1.5), 20);
+    }
+}
@@ -2,13 +2,39 @@ use crate::Mode;
 use crate::PathBuf;
 use crate::Settings;
 use crate::WeatherType;
+use crate::blend::BlendSettings;
+use crate::debounce::{Debounce, StabilitySettings};
+use crate::effects::EffectSettings;
+use crate::filter::ImageFilter;
+use crate::home_assistant::HomeAssistant;
+use crate::hooks::HookSettings;
+use crate::http::{HttpClient, HttpSettings};
+use crate::library::Library;
+use crate::location::{self, Location};
+use crate::location_source::{CommandSource, GeoClueSource, LocationFollower, LocationSource};
+use crate::overlay::{self, OverlaySettings};
+use crate::palette::{Format, PaletteSettings};
+use crate::rules::WeatherRule;
+use crate::scan::ScanOptions;
+use crate::secret::{self, ApiKey};
+use crate::transition::Transition;
+use crate::types::backend::Backend;
 use crate::types::daytime::Daytime;
+use crate::types::default_types::{COMMAND_TIMEOUT, GEOLOCATION_URL};
+use crate::types::forecastpolicy::ForecastPolicy;
+use crate::types::orientation::Orientation;
+use crate::types::position::Position;
+use crate::utils;
+use crate::weather_source::{MqttSettings, MqttSource, WeatherSource};
 
 use anyhow::anyhow;
 use anyhow::{Context, Result};
 
+use glob::Pattern;
 use ini::Ini;
+use reqwest::Url;
 use std::collections::HashMap;
+use std::time::Duration;
 
 // get interval and add 1 minute.
 fn fetch_timer(interval: &str) -> i32 {
@@ -74,22 +100,467 @@ fn fetch_weather_groups(
     custom_weather_groups: bool,
 ) -> HashMap<String, Vec<WeatherType>> {
     let mut weather_groups = HashMap::new();
-    if custom_weather_groups {
-        if let Some(weather_groups_section) = config.section(Some("weather-groups")) {
-            for (folder_name, weather_types_str) in weather_groups_section.iter() {
-                let weather_types: Vec<WeatherType> = weather_types_str
-                    .split_whitespace()
-                    .filter_map(|weather| WeatherType::get_weathertype(weather).ok())
-                    .collect();
-                if !weather_types.is_empty() {
-                    weather_groups.insert(folder_name.to_string(), weather_types);
-                }
+    if custom_weather_groups
+        && let Some(weather_groups_section) = config.section(Some("weather-groups"))
+    {
+        for (folder_name, weather_types_str) in weather_groups_section.iter() {
+            let weather_types: Vec<WeatherType> = weather_types_str
+                .split_whitespace()
+                .filter_map(|weather| WeatherType::get_weathertype(weather).ok())
+                .collect();
+            if !weather_types.is_empty() {
+                weather_groups.insert(folder_name.to_string(), weather_types);
             }
         }
     }
     weather_groups
 }
 
+// Fetch the location from [settings]. Coordinates are preferred over a city ID, a postal
+// code, IP geolocation and finally the city name.
+fn fetch_location(config: &Ini) -> Result<Location> {
+    let get = |key: &str| config.get_from(Some("settings"), key);
+    let country = get("country").unwrap_or_default().to_string();
+    if let (Some(lat), Some(lon)) = (get("lat"), get("lon")) {
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<f64>()
+                .with_context(|| format!("Invalid coordinate: {}", value))
+        };
+        return location::coordinates(parse(lat)?, parse(lon)?);
+    }
+    if let Some(id) = get("city-id") {
+        return Ok(Location::CityId(
+            id.trim()
+                .parse()
+                .with_context(|| format!("Invalid city-id: {}", id))?,
+        ));
+    }
+    if let Some(zip) = get("zip") {
+        return Ok(Location::Zip {
+            zip: zip.to_string(),
+            country,
+        });
+    }
+    if get("location") == Some("ip") {
+        return Ok(Location::Ip);
+    }
+    Ok(Location::City {
+        city: get("city").unwrap_or_default().to_string(),
+        country,
+    })
+}
+
+// Paths starting with ~/ are relative to the home directory.
+fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(relative) => home::home_dir().unwrap_or_default().join(relative),
+        None => PathBuf::from(path),
+    }
+}
+
+// Fetch a secret given as `name`, `name-file`, `name-command` or `name-keyring` in a
+// section, the first one set is used.
+fn fetch_secret(config: &Ini, section: &str, name: &str) -> Result<Option<ApiKey>> {
+    let get = |suffix: &str| config.get_from(Some(section), &format!("{}{}", name, suffix));
+    if let Some(secret) = get("") {
+        return Ok(Some(ApiKey::new(secret)));
+    }
+    if let Some(path) = get("-file") {
+        return secret::read_file(&expand_home(path)).map(Some);
+    }
+    // A password manager waiting for input is killed after the timeout. The output is left
+    // out of errors as it may contain the secret.
+    if let Some(command) = get("-command") {
+        return utils::run_command(command, Duration::from_secs(COMMAND_TIMEOUT))
+            .map(|output| Some(ApiKey::new(&output)))
+            .with_context(|| format!("Could not run {}-command", name));
+    }
+    if let Some(attributes) = get("-keyring") {
+        return secret::keyring(&secret::parse_attributes(attributes)?)
+            .with_context(|| format!("Could not read the {} from the keyring", name))
+            .map(Some);
+    }
+    Ok(None)
+}
+
+// Fetch the API key. The environment variable wins, then the first of key, key-file,
+// key-command and key-keyring in [settings]. Without any of them the key is empty.
+fn fetch_key(config: &Ini, env: Option<String>) -> Result<ApiKey> {
+    if let Some(key) = env.filter(|key| !key.trim().is_empty()) {
+        return Ok(ApiKey::new(&key));
+    }
+    Ok(fetch_secret(config, "settings", "key")?.unwrap_or_default())
+}
+
+// Fetch the weather source from [weather], OpenWeatherMap unless a local source is set.
+fn fetch_weather_source(config: &Ini) -> Result<WeatherSource> {
+    let get = |key: &str| config.get_from(Some("weather"), key);
+    let require = |key: &str, source: &str| {
+        get(key)
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("source = {} needs {} in [weather]", source, key))
+    };
+    Ok(match get("source").unwrap_or("openweathermap") {
+        "openweathermap" => WeatherSource::OpenWeatherMap,
+        "file" => WeatherSource::File(PathBuf::from(require("file", "file")?)),
+        "command" => WeatherSource::Command(require("command", "command")?),
+        "home-assistant" => WeatherSource::HomeAssistant,
+        "mqtt" => WeatherSource::Mqtt(MqttSource::new(MqttSettings {
+            host: get("host").unwrap_or("localhost").to_string(),
+            port: match get("port") {
+                Some(port) => port
+                    .parse()
+                    .with_context(|| format!("Invalid port: {}", port))?,
+                None => 1883,
+            },
+            topic: require("topic", "mqtt")?,
+            username: get("username").map(str::to_string),
+            password: fetch_secret(config, "weather", "password")?,
+        })),
+        source => return Err(anyhow!("not a known weather source: {}", source)),
+    })
+}
+
+// Fetch the Home Assistant instance from [home-assistant], None without the section.
+fn fetch_home_assistant(config: &Ini) -> Result<Option<HomeAssistant>> {
+    if config.section(Some("home-assistant")).is_none() {
+        return Ok(None);
+    }
+    let get = |key: &str| config.get_from(Some("home-assistant"), key);
+    let url = get("url").unwrap_or("http://homeassistant.local:8123/");
+    // Entity paths are joined to the url, which only works with a trailing slash.
+    let url = match url.ends_with('/') {
+        true => url.to_string(),
+        false => format!("{}/", url),
+    };
+    let url = Url::parse(&url).with_context(|| format!("Invalid url: {}", url))?;
+    let token = fetch_secret(config, "home-assistant", "token")?
+        .ok_or_else(|| anyhow!("[home-assistant] needs a token"))?;
+    let mut home_assistant = HomeAssistant::new(
+        url,
+        token,
+        get("entity").unwrap_or("weather.home").to_string(),
+    );
+    if let Some(entity) = get("sun-entity") {
+        home_assistant.sun_entity = entity.to_string();
+    }
+    home_assistant.next_entity = get("next-entity").map(str::to_string);
+    home_assistant.pause_entity = get("pause-entity").map(str::to_string);
+    Ok(Some(home_assistant))
+}
+
+// Fetch the hook commands from [hooks].
+fn fetch_hooks(config: &Ini) -> Result<HookSettings> {
+    let get = |key: &str| config.get_from(Some("hooks"), key).map(str::to_string);
+    let mut hooks = HookSettings {
+        on_wallpaper_change: get("on-wallpaper-change"),
+        on_daytime_change: get("on-daytime-change"),
+        on_weather_change: get("on-weather-change"),
+        ..Default::default()
+    };
+    if let Some(timeout) = get("timeout") {
+        hooks.timeout = timeout
+            .parse()
+            .with_context(|| format!("Invalid hook timeout: {}", timeout))?;
+    }
+    Ok(hooks)
+}
+
+// Fetch the HTTP client settings from [http].
+fn fetch_http(config: &Ini) -> Result<HttpSettings> {
+    let get = |key: &str| config.get_from(Some("http"), key);
+    let defaults = HttpSettings::default();
+    let get_seconds = |key: &str, default: u64| -> Result<u64> {
+        get(key).map_or(Ok(default), |value| {
+            value
+                .parse()
+                .with_context(|| format!("Invalid {}: {}", key, value))
+        })
+    };
+    Ok(HttpSettings {
+        connect_timeout: get_seconds("connect-timeout", defaults.connect_timeout)?,
+        timeout: get_seconds("timeout", defaults.timeout)?,
+        proxy: get("proxy").map(str::to_string),
+        base_url: get("base-url").map_or(defaults.base_url, str::to_string),
+        user_agent: get("user-agent").map_or(defaults.user_agent, str::to_string),
+    })
+}
+
+// Fetch the location source to follow from [location], None if following is off.
+fn fetch_follow(config: &Ini) -> Result<Option<LocationFollower>> {
+    let get = |key: &str| config.get_from(Some("location"), key);
+    let source: Box<dyn LocationSource> = match get("follow").unwrap_or("none") {
+        "none" => return Ok(None),
+        "geoclue" => Box::new(GeoClueSource::default()),
+        "command" => Box::new(CommandSource {
+            command: get("command")
+                .ok_or_else(|| anyhow!("follow = command needs a command in [location]"))?
+                .to_string(),
+        }),
+        source => return Err(anyhow!("not a known location source: {}", source)),
+    };
+    let min_distance = match get("min-distance") {
+        Some(distance) => distance
+            .parse()
+            .with_context(|| format!("Invalid min-distance: {}", distance))?,
+        None => 5.0,
+    };
+    let interval = match get("interval") {
+        Some(interval) => interval
+            .parse()
+            .with_context(|| format!("Invalid location interval: {}", interval))?,
+        None => 15,
+    };
+    Ok(Some(LocationFollower {
+        source,
+        min_distance,
+        interval,
+        timer: 0,
+    }))
+}
+
+// Parse the [weather-rules] section, rules keep the order of the config file.
+fn fetch_weather_rules(config: &Ini) -> Result<Vec<WeatherRule>> {
+    config
+        .section(Some("weather-rules"))
+        .map(|rules_section| {
+            rules_section
+                .iter()
+                .map(|(name, rule)| {
+                    WeatherRule::parse(name, rule)
+                        .with_context(|| format!("Invalid weather rule {} = {}", name, rule))
+                })
+                .collect()
+        })
+        .unwrap_or_else(|| Ok(Vec::new()))
+}
+
+// Parse a whitespace separated list of glob patterns.
+fn fetch_patterns(config: &Ini, key: &str) -> Result<Vec<Pattern>> {
+    config
+        .get_from(Some("scan"), key)
+        .unwrap_or("")
+        .split_whitespace()
+        .map(|pattern| {
+            Pattern::new(pattern).with_context(|| format!("Invalid {} pattern: {}", key, pattern))
+        })
+        .collect()
+}
+
+// Fetch the image discovery options from [scan].
+fn fetch_scan_options(config: &Ini) -> Result<ScanOptions> {
+    let mut options = ScanOptions::default();
+    let get_bool = |key: &str, default: bool| {
+        config
+            .get_from(Some("scan"), key)
+            .map_or(default, |value| value == "true")
+    };
+    options.recursive = get_bool("recursive", options.recursive);
+    options.hidden_files = get_bool("hidden-files", options.hidden_files);
+    options.follow_symlinks = get_bool("follow-symlinks", options.follow_symlinks);
+    if let Some(depth) = config.get_from(Some("scan"), "max-depth") {
+        options.max_depth = Some(
+            depth
+                .parse()
+                .with_context(|| format!("Invalid max-depth: {}", depth))?,
+        );
+    }
+    if let Some(extensions) = config.get_from(Some("scan"), "extensions") {
+        options.extensions = extensions
+            .split_whitespace()
+            .map(|ext| ext.trim_start_matches('.').to_lowercase())
+            .collect();
+    }
+    options.include = fetch_patterns(config, "include")?;
+    options.exclude = fetch_patterns(config, "exclude")?;
+    Ok(options)
+}
+
+// Fetch image filter rules, an empty [filter] section disables filtering.
+fn fetch_filter(config: &Ini) -> Result<ImageFilter> {
+    let get_number = |key: &str| -> Result<Option<u32>> {
+        config
+            .get_from(Some("filter"), key)
+            .map(|value| {
+                value
+                    .parse()
+                    .with_context(|| format!("Invalid {}: {}", key, value))
+            })
+            .transpose()
+    };
+    let aspect = match config.get_from(Some("filter"), "aspect") {
+        None | Some("any") => None,
+        Some(aspect) => Some(Orientation::from_string(aspect)?),
+    };
+    let aspect_tolerance = config
+        .get_from(Some("filter"), "aspect-tolerance")
+        .unwrap_or("0.1");
+    Ok(ImageFilter {
+        min_width: get_number("min-width")?,
+        min_height: get_number("min-height")?,
+        aspect,
+        match_output: config
+            .get_from(Some("filter"), "match-output")
+            .unwrap_or("false")
+            == "true",
+        aspect_tolerance: aspect_tolerance
+            .parse()
+            .with_context(|| format!("Invalid aspect-tolerance: {}", aspect_tolerance))?,
+    })
+}
+
+// Fetch the crossfade settings from [transition].
+fn fetch_transition(config: &Ini) -> Result<Transition> {
+    let mut transition = Transition {
+        crossfade: config
+            .get_from(Some("transition"), "crossfade")
+            .unwrap_or("false")
+            == "true",
+        ..Default::default()
+    };
+    if let Some(duration) = config.get_from(Some("transition"), "duration") {
+        transition.duration = duration
+            .parse()
+            .ok()
+            .filter(|duration: &f32| duration.is_finite() && *duration >= 0.0)
+            .ok_or_else(|| anyhow!("Invalid transition duration: {}", duration))?;
+    }
+    if let Some(frames) = config.get_from(Some("transition"), "frames") {
+        transition.frames = frames
+            .parse()
+            .with_context(|| format!("Invalid transition frames: {}", frames))?;
+    }
+    Ok(transition)
+}
+
+// Fetch the day to night blending settings from [blend].
+fn fetch_blend(config: &Ini) -> Result<BlendSettings> {
+    let mut blend = BlendSettings::default();
+    if let Some(interval) = config.get_from(Some("blend"), "interval") {
+        blend.interval = interval
+            .parse()
+            .with_context(|| format!("Invalid blend interval: {}", interval))?;
+    }
+    if let Some(buckets) = config.get_from(Some("blend"), "steps") {
+        blend.buckets = buckets
+            .parse()
+            .with_context(|| format!("Invalid blend steps: {}", buckets))?;
+    }
+    if let Some(twilight) = config.get_from(Some("blend"), "twilight") {
+        blend.twilight = twilight
+            .parse()
+            .with_context(|| format!("Invalid blend twilight: {}", twilight))?;
+    }
+    Ok(blend)
+}
+
+// Fetch the weather effect settings from [effects].
+fn fetch_effects(config: &Ini) -> Result<EffectSettings> {
+    let mut effects = EffectSettings {
+        enabled: config
+            .get_from(Some("effects"), "weather-effects")
+            .unwrap_or("false")
+            == "true",
+        ..Default::default()
+    };
+    if let Some(seed) = config.get_from(Some("effects"), "seed") {
+        effects.seed = seed
+            .parse()
+            .with_context(|| format!("Invalid effects seed: {}", seed))?;
+    }
+    if let Some(strength) = config.get_from(Some("effects"), "strength") {
+        effects.strength = strength
+            .parse()
+            .with_context(|| format!("Invalid effects strength: {}", strength))?;
+    }
+    Ok(effects)
+}
+
+// Fetch the debounce settings from [stability].
+fn fetch_stability(config: &Ini) -> Result<StabilitySettings> {
+    let mut stability = StabilitySettings {
+        ignore_same_group: config
+            .get_from(Some("stability"), "ignore-same-group")
+            .unwrap_or("false")
+            == "true",
+        ..Default::default()
+    };
+    if let Some(polls) = config.get_from(Some("stability"), "stable-polls") {
+        stability.polls = polls
+            .parse()
+            .with_context(|| format!("Invalid stable-polls: {}", polls))?;
+    }
+    if let Some(minutes) = config.get_from(Some("stability"), "stable-minutes") {
+        stability.minutes = minutes
+            .parse()
+            .with_context(|| format!("Invalid stable-minutes: {}", minutes))?;
+    }
+    if let Some(dwell) = config.get_from(Some("stability"), "min-dwell") {
+        stability.min_dwell = dwell
+            .parse()
+            .with_context(|| format!("Invalid min-dwell: {}", dwell))?;
+    }
+    Ok(stability)
+}
+
+// Fetch the text overlay settings from [overlay].
+fn fetch_overlay(config: &Ini) -> Result<OverlaySettings> {
+    let mut overlay = OverlaySettings {
+        enabled: config
+            .get_from(Some("overlay"), "enabled")
+            .unwrap_or("false")
+            == "true",
+        shadow: config.get_from(Some("overlay"), "shadow").unwrap_or("true") == "true",
+        font: config.get_from(Some("overlay"), "font").map(PathBuf::from),
+        ..Default::default()
+    };
+    if let Some(format) = config.get_from(Some("overlay"), "format") {
+        overlay.format = format.replace("\\n", "\n");
+    }
+    if let Some(position) = config.get_from(Some("overlay"), "position") {
+        overlay.position = Position::from_string(position)?;
+    }
+    if let Some(size) = config.get_from(Some("overlay"), "size") {
+        overlay.size = size
+            .parse()
+            .with_context(|| format!("Invalid overlay size: {}", size))?;
+    }
+    if let Some(colour) = config.get_from(Some("overlay"), "colour") {
+        overlay.colour = overlay::parse_colour(colour)?;
+    }
+    Ok(overlay)
+}
+
+// Fetch the colour palette settings from [palette].
+fn fetch_palette(config: &Ini) -> Result<PaletteSettings> {
+    let get = |key: &str| config.get_from(Some("palette"), key);
+    let mut palette = PaletteSettings {
+        enabled: get("enabled").unwrap_or("false") == "true",
+        template_dir: get("template-dir").map(expand_home),
+        ..Default::default()
+    };
+    if let Some(colours) = get("colours") {
+        palette.colours = colours
+            .parse()
+            .ok()
+            .filter(|colours| (1..=16).contains(colours))
+            .ok_or_else(|| anyhow!("Invalid palette colours, expected 1 to 16: {}", colours))?;
+    }
+    if let Some(formats) = get("formats") {
+        palette.formats = formats
+            .split(',')
+            .filter(|format| !format.trim().is_empty())
+            .map(Format::from_string)
+            .collect::<Result<_>>()?;
+    }
+    if let Some(output) = get("output") {
+        palette.output = expand_home(output);
+    }
+    Ok(palette)
+}
+
 fn fetch_modes(config: &Ini) -> Vec<Mode> {
     config
         .section(Some("modes"))
@@ -115,20 +586,23 @@ pub fn fetch_config(config_path: PathBuf) -> Result<Settings> {
         .with_context(|| format!("Failed to load config file: {}", config_path.display()))?;
     // Parse config
     // Fetch API key
-    let key = config
-        .get_from(Some("settings"), "key")
-        .unwrap_or_default()
-        .to_string();
-    // Fetch city
-    let city = config
-        .get_from(Some("settings"), "city")
-        .unwrap_or_default()
-        .to_string();
-    // Fetch countru
-    let country = config
-        .get_from(Some("settings"), "country")
-        .unwrap_or_default()
+    let key = fetch_key(&config, std::env::var(secret::KEY_ENV).ok())?;
+    // Build the HTTP client shared by all requests
+    let http = HttpClient::new(&fetch_http(&config)?)?;
+    let weather_source = fetch_weather_source(&config)?;
+    let home_assistant = fetch_home_assistant(&config)?;
+    if matches!(weather_source, WeatherSource::HomeAssistant) && home_assistant.is_none() {
+        return Err(anyhow!(
+            "source = home-assistant needs a [home-assistant] section"
+        ));
+    }
+    // Fetch location
+    let location = fetch_location(&config)?;
+    let geolocation_url = config
+        .get_from(Some("settings"), "geolocation-url")
+        .unwrap_or(GEOLOCATION_URL)
         .to_string();
+    let follow = fetch_follow(&config)?;
     // Fetch path
     // Should panic if no path is found
     let path: Result<String, anyhow::Error> = config
@@ -157,8 +631,40 @@ pub fn fetch_config(config_path: PathBuf) -> Result<Settings> {
         == "true";
     // Fetch folder names
     let folder_names = fetch_folder_names(&config, custom_folder_names);
+    // Fetch default fallback folder
+    let default_folder = config
+        .get_from(Some("folders"), "default-folder")
+        .map(|s| s.to_string());
+    // Fetch tag mode(bool)
+    let tag_mode = config.get_from(Some("tags"), "tag-mode").unwrap_or("false") == "true";
+    // Fetch tag index location, defaults to a hidden file in the wallpaper folder
+    let index_file = config.get_from(Some("tags"), "index-file").map_or_else(
+        || PathBuf::from(&path).join(".fehther-index.json"),
+        PathBuf::from,
+    );
+    // Fetch library database location
+    let library_file = config
+        .get_from(Some("library"), "database")
+        .map_or_else(Library::database_file, PathBuf::from);
+    // Fetch library watch(bool)
+    let library_watch = config.get_from(Some("library"), "watch").unwrap_or("false") == "true";
+    // Fetch library rescan interval, 0 disables periodic rescans
+    let rescan_interval = config
+        .get_from(Some("library"), "rescan-interval")
+        .unwrap_or("0");
+    let rescan_interval = rescan_interval
+        .parse::<i32>()
+        .with_context(|| format!("Invalid rescan-interval: {}", rescan_interval))?;
+    // Fetch image discovery options
+    let scan = fetch_scan_options(&config)?;
+    // Fetch image filter rules
+    let filter = fetch_filter(&config)?;
     // Fetch weather groups
     let weather_groups = fetch_weather_groups(&config, custom_weather_groups);
+    // Fetch weather rules
+    let weather_rules = fetch_weather_rules(&config)?;
+    // Fetch weather debounce settings
+    let stability = fetch_stability(&config)?;
     // Fetch sunset timer
     let sunset_timer = fetch_timer(
         config
@@ -176,27 +682,119 @@ pub fn fetch_config(config_path: PathBuf) -> Result<Settings> {
         "--bg-{}",
         config.get_from(Some("modes"), "feh-mode").unwrap_or("fill")
     );
+    // Fetch forecast mode settings
+    let forecast_horizon = match config.get_from(Some("modes"), "forecast-horizon") {
+        Some(hours) => hours
+            .parse()
+            .with_context(|| format!("Invalid forecast-horizon: {}", hours))?,
+        None => 0,
+    };
+    let forecast_policy = ForecastPolicy::from_string(
+        config
+            .get_from(Some("modes"), "forecast-policy")
+            .unwrap_or("worst"),
+    )?;
+    // Forecasts are only available from OpenWeatherMap.
+    if forecast_horizon > 0 && !matches!(weather_source, WeatherSource::OpenWeatherMap) {
+        return Err(anyhow!(
+            "forecast-horizon needs the openweathermap weather source"
+        ));
+    }
+    // Local sources don't know sunrise and sunset, they are calculated from coordinates.
+    let is_local_source = matches!(
+        weather_source,
+        WeatherSource::File(_) | WeatherSource::Command(_) | WeatherSource::Mqtt(_)
+    );
+    let is_sun_needed = modes.contains(&Mode::Daytime) || modes.contains(&Mode::Blend);
+    let has_coordinates =
+        matches!(location, Location::Coordinates { .. } | Location::Ip) || follow.is_some();
+    if is_local_source && is_sun_needed && !has_coordinates {
+        return Err(anyhow!(
+            "daytime and blend mode with a local weather source need lat and lon, location = ip or a follow source in [location]"
+        ));
+    }
+
+    // Fetch wallpaper backend
+    let backend = Backend::from_string(config.get_from(Some("modes"), "backend").unwrap_or("feh"))?;
+    // Fetch crossfade settings
+    let transition = fetch_transition(&config)?;
+    // Fetch day to night blending settings
+    let blend = fetch_blend(&config)?;
+    // Fetch weather effect settings
+    let effects = fetch_effects(&config)?;
+    // Fetch text overlay settings
+    let overlay = fetch_overlay(&config)?;
+    // Fetch hook commands
+    let hooks = fetch_hooks(&config)?;
+    // Fetch colour palette settings
+    let palette = fetch_palette(&config)?;
 
     // Load config into Settings
     Ok(Settings {
         current_loop: true,
         key,
-        city,
-        country,
+        http,
+        weather_source,
+        home_assistant,
+        paused: false,
+        dbus: config.get_from(Some("dbus"), "enabled").unwrap_or("false") == "true",
+        location,
+        geolocation_url,
+        follow,
+        timezone: None,
         path,
         modes,
         recovery_mode: false,
+        weather_backoff: 0,
+        weather_stopped: false,
         disabled_daytimes,
         custom_weather_groups,
         folder_names,
+        default_folder,
+        tag_mode,
+        index_file,
+        library_file,
+        library_watch,
+        rescan_interval,
+        rescan_timer: 0,
+        scan,
+        filter,
         weather_groups,
+        weather_rules,
+        weather_rule: None,
+        debounce: Debounce::new(stability),
+        forecast_horizon,
+        forecast_policy,
+        forecast: Vec::new(),
+        forecast_timer: 0,
         sunset_timer,
         golden_hour,
         interval,
         daytime: Daytime::Day,
         weather: WeatherType::Clear,
         feh_mode,
+        backend,
+        transition,
         timer: 0,
+        current_images: Vec::new(),
+        shown: Vec::new(),
+        history: Vec::new(),
+        override_folder: None,
+        blend,
+        blend_scene: None,
+        blend_bucket: None,
+        blend_timer: 0,
+        coordinates: None,
+        sun_times: None,
+        effects,
+        precipitation: 0.0,
+        overlay,
+        hooks,
+        palette,
+        observation: None,
+        overlay_base: Vec::new(),
+        overlay_outputs: Vec::new(),
+        overlay_text: String::new(),
     })
 }
 
@@ -236,9 +834,14 @@ fn fetch_config_test_1() -> Result<()> {
     assert_eq!(settings.disabled_daytimes, expected_disabled_daytimes);
     assert_eq!(settings.weather_groups, expected_weather_groups);
     assert_eq!(settings.folder_names, expected_folder_names);
-    assert_eq!(settings.key, "fake-key".to_string());
-    assert_eq!(settings.city, "london".to_string());
-    assert_eq!(settings.country, "UK".to_string());
+    assert_eq!(settings.key.expose(), "fake-key");
+    assert_eq!(
+        settings.location,
+        Location::City {
+            city: "london".to_string(),
+            country: "UK".to_string()
+        }
+    );
     assert_eq!(settings.path, "/home/user/files/documents/wallpapers");
 
     Ok(())
@@ -267,6 +870,8 @@ fn fetch_config_test_2() -> Result<()> {
     assert_eq!(settings.modes, expected_modes);
     assert_eq!(settings.weather_groups, expected_weather_groups);
     assert_eq!(settings.folder_names, expected_folder_names);
+    assert!(!settings.library_watch);
+    assert!(!settings.dbus);
 
     Ok(())
 }
@@ -309,3 +914,238 @@ fn fetch_config_test_4() -> Result<()> {
     }
     Ok(())
 }
+
+#[test]
+// testing image discovery and filter options
+fn fetch_config_test_5() -> Result<()> {
+    let config_path = PathBuf::from(r"./test_config/test_5.ini");
+    let settings: Settings = fetch_config(config_path)?;
+
+    assert!(settings.scan.recursive);
+    assert_eq!(settings.scan.max_depth, Some(2));
+    assert_eq!(settings.scan.extensions, vec!["jpg", "png"]);
+    assert_eq!(
+        settings.scan.include,
+        vec![Pattern::new("*.jpg")?, Pattern::new("landscapes/**")?]
+    );
+    assert_eq!(settings.scan.exclude, vec![Pattern::new("thumbnails")?]);
+    assert!(settings.scan.hidden_files);
+    assert!(!settings.scan.follow_symlinks);
+    assert_eq!(settings.filter.min_width, Some(1920));
+    assert_eq!(settings.filter.min_height, None);
+    assert_eq!(settings.filter.aspect, Some(Orientation::Landscape));
+    assert!(settings.filter.match_output);
+    assert!(settings.overlay.enabled);
+    assert_eq!(settings.overlay.format, "{temp}\n{time}");
+    assert_eq!(settings.overlay.position, Position::TopLeft);
+    assert_eq!(settings.overlay.size, 40.0);
+    assert_eq!(settings.overlay.colour, [255, 0, 0, 128]);
+    assert!(settings.overlay.shadow);
+    let rule_names: Vec<&str> = settings
+        .weather_rules
+        .iter()
+        .map(|rule| rule.name.as_str())
+        .collect();
+    assert_eq!(rule_names, vec!["hot", "freezing"]);
+    assert_eq!(
+        settings.location,
+        Location::Coordinates {
+            lat: 52.37,
+            lon: 4.89
+        }
+    );
+    assert_eq!(settings.forecast_horizon, 3);
+    assert_eq!(settings.forecast_policy, ForecastPolicy::NextHour);
+    assert_eq!(
+        settings.http.base_url.as_str(),
+        "http://localhost:8080/owm/"
+    );
+
+    Ok(())
+}
+
+#[test]
+// testing that invalid weather rules fail at config load
+fn fetch_config_test_6() -> Result<()> {
+    let config_path = PathBuf::from(r"./test_config/test_6.ini");
+    let error = fetch_config(config_path).unwrap_err();
+    assert_eq!(error.to_string(), "Invalid weather rule windy = wind > 10");
+    assert!(format!("{:#}", error).contains("unknown field 'wind'"));
+    Ok(())
+}
+
+#[test]
+// testing the [http] section
+fn fetch_http_test() -> Result<()> {
+    let config = Ini::load_from_file("./test_config/test_5.ini")?;
+    let http = fetch_http(&config)?;
+    assert_eq!(http.timeout, 5);
+    assert_eq!(http.connect_timeout, 10);
+    assert_eq!(http.proxy, None);
+    assert!(http.user_agent.starts_with("fehther/"));
+    Ok(())
+}
+
+#[test]
+// testing the API key sources and that the key stays out of Debug output
+fn fetch_key_test() -> Result<()> {
+    let config = Ini::load_from_file("./test_config/test_7.ini")?;
+    let key = fetch_key(&config, None)?;
+    assert_eq!(key.expose(), "from-command");
+    assert_eq!(
+        fetch_key(&config, Some("from-env".to_string()))?.expose(),
+        "from-env"
+    );
+    // Output of a failing command stays out of the error.
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("owm");
+    std::fs::write(&path, "leaked-key\n")?;
+    let config = Ini::load_from_str(&format!(
+        "[settings]\nkey-command = cat {}; exit 2\n",
+        path.display()
+    ))?;
+    let error = fetch_key(&config, None).unwrap_err();
+    assert!(
+        !format!("{:#}", error).contains("leaked-key"),
+        "{:#}",
+        error
+    );
+    let settings = fetch_config(PathBuf::from(r"./test_config/test_1.ini"))?;
+    assert!(!format!("{:?}", settings).contains("fake-key"));
+    Ok(())
+}
+
+#[test]
+// testing local weather sources
+fn fetch_weather_source_test() -> Result<()> {
+    let settings = fetch_config(PathBuf::from(r"./test_config/test_8.ini"))?;
+    let WeatherSource::Mqtt(source) = settings.weather_source else {
+        panic!("expected an mqtt source");
+    };
+    assert_eq!(
+        source.settings,
+        MqttSettings {
+            host: "station.local".to_string(),
+            port: 1883,
+            topic: "home/weather".to_string(),
+            username: Some("fehther".to_string()),
+            password: Some(ApiKey::new("mqtt-secret")),
+        }
+    );
+    assert!(!format!("{:?}", source).contains("mqtt-secret"));
+    let settings = fetch_config(PathBuf::from(r"./test_config/test_1.ini"))?;
+    assert!(matches!(
+        settings.weather_source,
+        WeatherSource::OpenWeatherMap
+    ));
+    let config = Ini::load_from_str("[weather]\nsource = file\n")?;
+    assert!(fetch_weather_source(&config).is_err());
+    Ok(())
+}
+
+#[test]
+// Local sources need coordinates for sun times and cannot provide a forecast
+fn local_source_checks_test() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let config_path = dir.path().join("config.ini");
+    let load = |extra: &str| {
+        std::fs::write(
+            &config_path,
+            format!(
+                "[settings]\npath = /home/user/wallpapers\n{}\n[weather]\nsource = command\ncommand = cat reading.json\n",
+                extra
+            ),
+        )
+        .unwrap();
+        fetch_config(config_path.clone())
+    };
+    assert!(load("").is_ok());
+    let error = load("[modes]\ndaytime-mode = true\n").unwrap_err();
+    assert!(error.to_string().contains("need lat and lon"), "{}", error);
+    assert!(load("lat = 52.37\nlon = 4.89\n[modes]\ndaytime-mode = true\n").is_ok());
+    assert!(load("[modes]\nweather-mode = true\nforecast-horizon = 3\n").is_err());
+    Ok(())
+}
+
+#[test]
+fn fetch_transition_test() -> Result<()> {
+    let transition = fetch_transition(&Ini::load_from_str(
+        "[transition]\ncrossfade = true\nduration = 0.5\nframes = 6\n",
+    )?)?;
+    assert!(transition.crossfade);
+    assert_eq!(transition.duration, 0.5);
+    assert_eq!(transition.frames, 6);
+    assert_eq!(
+        fetch_transition(&Ini::load_from_str("")?)?,
+        Transition::default()
+    );
+    for duration in ["inf", "NaN", "-1", "soon"] {
+        let config = Ini::load_from_str(&format!("[transition]\nduration = {}\n", duration))?;
+        assert!(fetch_transition(&config).is_err(), "{}", duration);
+    }
+    Ok(())
+}
+
+#[test]
+fn fetch_palette_test() -> Result<()> {
+    let config = Ini::load_from_str(
+        "[palette]\nenabled = true\ncolours = 16\nformats = json, shell\noutput = /tmp/palette\n",
+    )?;
+    let palette = fetch_palette(&config)?;
+    assert!(palette.enabled);
+    assert_eq!(palette.colours, 16);
+    assert_eq!(palette.formats, vec![Format::Json, Format::Shell]);
+    assert_eq!(palette.output, PathBuf::from("/tmp/palette"));
+    assert_eq!(palette.template_dir, None);
+    assert!(!fetch_palette(&Ini::load_from_str("")?)?.enabled);
+    assert!(fetch_palette(&Ini::load_from_str("[palette]\ncolours = 0\n")?).is_err());
+    assert!(fetch_palette(&Ini::load_from_str("[palette]\nformats = toml\n")?).is_err());
+    Ok(())
+}
+
+#[test]
+fn fetch_hooks_test() -> Result<()> {
+    let config = Ini::load_from_str(
+        "[hooks]\non-wallpaper-change = wal -i \"$FEHTHER_IMAGE\"\ntimeout = 10\n",
+    )?;
+    let hooks = fetch_hooks(&config)?;
+    assert_eq!(
+        hooks.on_wallpaper_change.as_deref(),
+        Some("wal -i \"$FEHTHER_IMAGE\"")
+    );
+    assert_eq!(hooks.on_daytime_change, None);
+    assert_eq!(hooks.timeout, 10);
+    assert_eq!(
+        fetch_hooks(&Ini::load_from_str("")?)?,
+        HookSettings::default()
+    );
+    assert!(fetch_hooks(&Ini::load_from_str("[hooks]\ntimeout = soon\n")?).is_err());
+    Ok(())
+}
+
+#[test]
+fn fetch_home_assistant_test() -> Result<()> {
+    let settings = fetch_config(PathBuf::from(r"./test_config/test_9.ini"))?;
+    assert!(matches!(
+        settings.weather_source,
+        WeatherSource::HomeAssistant
+    ));
+    let home_assistant = settings.home_assistant.unwrap();
+    assert_eq!(home_assistant.url.as_str(), "http://ha.local:8123/");
+    assert_eq!(home_assistant.token.expose(), "ha-token");
+    assert_eq!(home_assistant.entity, "weather.forecast_home");
+    assert_eq!(home_assistant.sun_entity, "sun.sun");
+    assert_eq!(home_assistant.next_entity, None);
+    assert_eq!(
+        home_assistant.pause_entity.as_deref(),
+        Some("input_boolean.fehther_pause")
+    );
+    assert!(
+        fetch_config(PathBuf::from(r"./test_config/test_1.ini"))?
+            .home_assistant
+            .is_none()
+    );
+    let config = Ini::load_from_str("[home-assistant]\nurl = http://ha.local:8123\n")?;
+    assert!(fetch_home_assistant(&config).is_err());
+    Ok(())
+}
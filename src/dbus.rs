@@ -0,0 +1,307 @@
+use crate::settings::Settings;
+use anyhow::Result;
+use std::fmt;
+use tokio::sync::{mpsc, oneshot};
+use zbus::object_server::SignalEmitter;
+use zbus::{Connection, connection, fdo, interface};
+
+// Well-known name and object path of the service on the session bus.
+pub const NAME: &str = "org.fehther.Daemon";
+pub const PATH: &str = "/org/fehther/Daemon";
+
+// Requests from D-Bus clients, handled by the main loop.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Next,
+    Previous,
+    Pause,
+    Resume,
+    Reload,
+    // Folder relative to the wallpaper path, empty to clear.
+    SetOverride(String),
+}
+
+// Logged when a command arrives.
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Next => write!(f, "next"),
+            Command::Previous => write!(f, "previous"),
+            Command::Pause => write!(f, "pause"),
+            Command::Resume => write!(f, "resume"),
+            Command::Reload => write!(f, "reload"),
+            Command::SetOverride(folder) if folder.is_empty() => write!(f, "clear override"),
+            Command::SetOverride(folder) => write!(f, "override {}", folder),
+        }
+    }
+}
+
+// Command with the channel for its result, an error is returned to the client.
+pub type Request = (Command, oneshot::Sender<Result<(), String>>);
+
+// Object exported at PATH. Properties mirror the state in Settings and are updated by
+// the main loop through Service::publish.
+pub struct Daemon {
+    commands: mpsc::Sender<Request>,
+    current_image: String,
+    daytime: String,
+    weather: String,
+    paused: bool,
+    override_folder: String,
+}
+
+impl Daemon {
+    async fn send(&self, command: Command) -> fdo::Result<()> {
+        let (reply, result) = oneshot::channel();
+        self.commands
+            .send((command, reply))
+            .await
+            .map_err(|_| fdo::Error::Failed("fehther is shutting down".to_string()))?;
+        result
+            .await
+            .map_err(|_| fdo::Error::Failed("fehther is shutting down".to_string()))?
+            .map_err(fdo::Error::Failed)
+    }
+}
+
+#[interface(name = "org.fehther.Daemon")]
+impl Daemon {
+    // Pick a new wallpaper from the current folder.
+    async fn next(&self) -> fdo::Result<()> {
+        self.send(Command::Next).await
+    }
+
+    // Go back to the wallpaper before the current one.
+    async fn previous(&self) -> fdo::Result<()> {
+        self.send(Command::Previous).await
+    }
+
+    // Leave the wallpaper alone until resumed.
+    async fn pause(&self) -> fdo::Result<()> {
+        self.send(Command::Pause).await
+    }
+
+    async fn resume(&self) -> fdo::Result<()> {
+        self.send(Command::Resume).await
+    }
+
+    // Read config.ini again.
+    async fn reload(&self) -> fdo::Result<()> {
+        self.send(Command::Reload).await
+    }
+
+    // Use a folder ahead of all others, an empty folder clears the override.
+    async fn set_override(&self, folder: String) -> fdo::Result<()> {
+        self.send(Command::SetOverride(folder)).await
+    }
+
+    // Image picked for the first output, before effects and overlay.
+    #[zbus(property)]
+    fn current_image(&self) -> String {
+        self.current_image.clone()
+    }
+
+    #[zbus(property)]
+    fn daytime(&self) -> String {
+        self.daytime.clone()
+    }
+
+    #[zbus(property)]
+    fn weather(&self) -> String {
+        self.weather.clone()
+    }
+
+    #[zbus(property)]
+    fn paused(&self) -> bool {
+        self.paused
+    }
+
+    #[zbus(property, name = "Override")]
+    fn override_folder(&self) -> String {
+        self.override_folder.clone()
+    }
+
+    #[zbus(signal)]
+    async fn wallpaper_changed(emitter: &SignalEmitter<'_>, image: &str) -> zbus::Result<()>;
+}
+
+// Connection owning NAME, with the receiving end of the command channel.
+pub struct Service {
+    connection: Connection,
+    pub requests: mpsc::Receiver<Request>,
+}
+
+impl Service {
+    // Export the daemon on the session bus.
+    pub async fn start() -> Result<Service> {
+        Service::serve(connection::Builder::session()?).await
+    }
+
+    async fn serve(builder: connection::Builder<'_>) -> Result<Service> {
+        let (commands, requests) = mpsc::channel(8);
+        let daemon = Daemon {
+            commands,
+            current_image: String::new(),
+            daytime: String::new(),
+            weather: String::new(),
+            paused: false,
+            override_folder: String::new(),
+        };
+        let connection = builder.name(NAME)?.serve_at(PATH, daemon)?.build().await?;
+        Ok(Service {
+            connection,
+            requests,
+        })
+    }
+
+    // Update the properties from the settings, emitting signals for the ones that changed.
+    pub async fn publish(&self, settings: &Settings) -> Result<()> {
+        let daemon = self
+            .connection
+            .object_server()
+            .interface::<_, Daemon>(PATH)
+            .await?;
+        let emitter = daemon.signal_emitter();
+        let mut state = daemon.get_mut().await;
+        let current_image = settings.shown.first().cloned().unwrap_or_default();
+        if state.current_image != current_image {
+            state.current_image = current_image;
+            state.current_image_changed(emitter).await?;
+            Daemon::wallpaper_changed(emitter, &state.current_image).await?;
+        }
+        let daytime = settings.daytime.to_string();
+        if state.daytime != daytime {
+            state.daytime = daytime;
+            state.daytime_changed(emitter).await?;
+        }
+        let weather = settings.current_weather().to_string();
+        if state.weather != weather {
+            state.weather = weather;
+            state.weather_changed(emitter).await?;
+        }
+        if state.paused != settings.paused {
+            state.paused = settings.paused;
+            state.paused_changed(emitter).await?;
+        }
+        let override_folder = settings.override_folder.clone().unwrap_or_default();
+        if state.override_folder != override_folder {
+            state.override_folder = override_folder;
+            state.override_changed(emitter).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use std::io::{BufRead, BufReader};
+    use std::path::PathBuf;
+    use std::process::{Child, Command as Process, Stdio};
+
+    #[zbus::proxy(
+        interface = "org.fehther.Daemon",
+        default_service = "org.fehther.Daemon",
+        default_path = "/org/fehther/Daemon"
+    )]
+    trait Client {
+        fn next(&self) -> zbus::Result<()>;
+        fn set_override(&self, folder: &str) -> zbus::Result<()>;
+        #[zbus(property)]
+        fn current_image(&self) -> zbus::Result<String>;
+        #[zbus(property)]
+        fn weather(&self) -> zbus::Result<String>;
+        #[zbus(property)]
+        fn paused(&self) -> zbus::Result<bool>;
+        #[zbus(signal)]
+        fn wallpaper_changed(&self, image: String) -> zbus::Result<()>;
+    }
+
+    // Private session bus, stopped when dropped.
+    struct Bus(Child, String);
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            self.0.kill().ok();
+            self.0.wait().ok();
+        }
+    }
+
+    fn private_bus() -> Option<Bus> {
+        let mut child = Process::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let mut address = String::new();
+        BufReader::new(child.stdout.take()?)
+            .read_line(&mut address)
+            .ok()?;
+        Some(Bus(child, address.trim().to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_service() {
+        let Some(bus) = private_bus() else {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        };
+        let mut service = Service::serve(connection::Builder::address(bus.1.as_str()).unwrap())
+            .await
+            .unwrap();
+        let connection = connection::Builder::address(bus.1.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let client = ClientProxy::new(&connection).await.unwrap();
+
+        // Methods wait for the main loop and return its result.
+        let call = tokio::spawn({
+            let client = client.clone();
+            async move { client.next().await }
+        });
+        let (command, reply) = service.requests.recv().await.unwrap();
+        assert_eq!(command, Command::Next);
+        reply.send(Ok(())).unwrap();
+        call.await.unwrap().unwrap();
+
+        let call = tokio::spawn({
+            let client = client.clone();
+            async move { client.set_override("missing").await }
+        });
+        let (command, reply) = service.requests.recv().await.unwrap();
+        assert_eq!(command, Command::SetOverride("missing".to_string()));
+        reply
+            .send(Err("no such folder: missing".to_string()))
+            .unwrap();
+        let error = call.await.unwrap().unwrap_err();
+        assert!(error.to_string().contains("no such folder"), "{}", error);
+
+        // Published state shows up as properties and signals.
+        let mut changes = client.receive_wallpaper_changed().await.unwrap();
+        let mut settings =
+            crate::config::fetch_config(PathBuf::from(r"./test_config/test_1.ini")).unwrap();
+        settings.shown = vec!["/wallpapers/day/lake.jpg".to_string()];
+        settings.paused = true;
+        // Outside weather mode the weather comes from the last observation.
+        settings
+            .modes
+            .retain(|mode| *mode != crate::types::modes::Mode::Weather);
+        settings.observation = Some(crate::fetch_weather::Observation {
+            weather: Some(crate::types::weathertype::WeatherType::LightRain),
+            ..Default::default()
+        });
+        service.publish(&settings).await.unwrap();
+        let change = changes.next().await.unwrap();
+        assert_eq!(change.args().unwrap().image, "/wallpapers/day/lake.jpg");
+        assert_eq!(
+            client.current_image().await.unwrap(),
+            "/wallpapers/day/lake.jpg"
+        );
+        assert!(client.paused().await.unwrap());
+        assert_eq!(client.weather().await.unwrap(), "light rain");
+    }
+}
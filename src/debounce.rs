@@ -0,0 +1,147 @@
+// Stability settings for weather changes, parsed from the [stability] section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StabilitySettings {
+    // Number of polls a new condition must be observed in a row.
+    pub polls: u32,
+    // Minutes a new condition must persist.
+    pub minutes: u64,
+    // Minimum minutes a wallpaper stays before the weather may change it.
+    pub min_dwell: u64,
+    // Weather changes that keep the same folder do not change the wallpaper.
+    pub ignore_same_group: bool,
+}
+
+impl Default for StabilitySettings {
+    fn default() -> Self {
+        StabilitySettings {
+            polls: 1,
+            minutes: 0,
+            min_dwell: 0,
+            ignore_same_group: false,
+        }
+    }
+}
+
+// Holds back a new value until it has been stable long enough. The first value is
+// accepted right away, so the initial wallpaper matches the weather.
+#[derive(Debug, Clone)]
+pub struct Debounce<T> {
+    pub settings: StabilitySettings,
+    seen: bool,
+    // Value waiting to be accepted, with the time and number of polls it has been seen.
+    pending: Option<(T, u64, u32)>,
+    last_change: u64,
+}
+
+impl<T: PartialEq> Debounce<T> {
+    pub fn new(settings: StabilitySettings) -> Debounce<T> {
+        Debounce {
+            settings,
+            seen: false,
+            pending: None,
+            last_change: 0,
+        }
+    }
+
+    // Record that the wallpaper changed, for the minimum dwell time.
+    pub fn changed(&mut self, now: u64) {
+        self.last_change = now;
+    }
+
+    // Feed the latest observed value. Returns it when it differs from the current value
+    // and has been stable long enough, in which case it should be applied.
+    pub fn update(&mut self, current: &T, observed: T, now: u64) -> Option<T> {
+        let first = !self.seen;
+        self.seen = true;
+        if observed == *current {
+            self.pending = None;
+            return None;
+        }
+        if first {
+            return Some(observed);
+        }
+        let (since, polls) = match self.pending.take() {
+            Some((value, since, polls)) if value == observed => (since, polls + 1),
+            _ => (now, 1),
+        };
+        let stable =
+            polls >= self.settings.polls && now.saturating_sub(since) >= self.settings.minutes * 60;
+        let dwelled = now.saturating_sub(self.last_change) >= self.settings.min_dwell * 60;
+        if stable && dwelled {
+            Some(observed)
+        } else {
+            self.pending = Some((observed, since, polls));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feed one value per minute and return the value after each poll.
+    fn run(settings: StabilitySettings, observed: &[u32]) -> Vec<u32> {
+        let mut debounce = Debounce::new(settings);
+        let mut current = 0;
+        observed
+            .iter()
+            .enumerate()
+            .map(|(minute, value)| {
+                let now = 1_000_000 + minute as u64 * 60;
+                if let Some(value) = debounce.update(&current, *value, now) {
+                    current = value;
+                    debounce.changed(now);
+                }
+                current
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_default_follows_every_change() {
+        let observed = [802, 803, 802, 803];
+        assert_eq!(run(StabilitySettings::default(), &observed), observed);
+    }
+
+    #[test]
+    fn test_alternating_conditions_are_ignored() {
+        let settings = StabilitySettings {
+            polls: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            run(settings.clone(), &[802, 803, 802, 803, 802, 803, 802]),
+            [802; 7]
+        );
+        assert_eq!(
+            run(settings, &[802, 803, 803, 802, 803, 803, 803, 803]),
+            [802, 802, 802, 802, 802, 802, 803, 803]
+        );
+    }
+
+    #[test]
+    fn test_stability_minutes() {
+        let settings = StabilitySettings {
+            minutes: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            run(settings, &[802, 803, 803, 802, 803, 803, 803]),
+            [802, 802, 802, 802, 802, 802, 803]
+        );
+    }
+
+    #[test]
+    fn test_min_dwell() {
+        let settings = StabilitySettings {
+            min_dwell: 3,
+            ..Default::default()
+        };
+        // The first value is accepted at minute 0, the next change waits until minute 3.
+        assert_eq!(
+            run(settings, &[802, 803, 803, 803, 500, 500, 500, 800]),
+            [802, 802, 802, 803, 803, 803, 500, 500]
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use crate::transition;
+use crate::types::weathertype::WeatherType;
+use crate::utils;
+use anyhow::Result;
+use image::RgbImage;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::Path;
+
+// Weather overlay settings, parsed from the [effects] section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectSettings {
+    pub enabled: bool,
+    // Seed for the random placement of rain streaks and snow flakes.
+    pub seed: u64,
+    // Multiplier for the intensity of all effects.
+    pub strength: f32,
+}
+
+impl Default for EffectSettings {
+    fn default() -> Self {
+        EffectSettings {
+            enabled: false,
+            seed: 1,
+            strength: 1.0,
+        }
+    }
+}
+
+// Small xorshift generator, the output only depends on the seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // Zero is a fixed point of xorshift.
+        Rng(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // Uniform value in 0.0..1.0
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+// Intensity between 0.0 and 1.0 from precipitation in mm/h. Light conditions without a
+// measured amount still get a visible effect.
+pub fn intensity(weather: &WeatherType, precipitation: f64) -> f32 {
+    let baseline = match weather {
+        WeatherType::LightDrizzle => 0.1,
+        WeatherType::Drizzle => 0.2,
+        WeatherType::HeavyDrizzle | WeatherType::LightRain | WeatherType::LightSnow => 0.3,
+        WeatherType::Rain | WeatherType::Snow | WeatherType::Sleet => 0.4,
+        WeatherType::Thunder | WeatherType::ShowerRain | WeatherType::ShowerSnow => 0.6,
+        WeatherType::HeavyRain | WeatherType::HeavySnow | WeatherType::HeavyThunder => 0.8,
+        WeatherType::Fog => 0.8,
+        _ => 0.5,
+    };
+    (baseline + precipitation as f32 / 10.0).min(1.0)
+}
+
+// Draw the effect for the current weather onto the image. Returns false if the weather has
+// no effect, in which case the image is left untouched.
+pub fn render(
+    image: &mut RgbImage,
+    weather: &WeatherType,
+    precipitation: f64,
+    settings: &EffectSettings,
+) -> bool {
+    let intensity = (intensity(weather, precipitation) * settings.strength).clamp(0.0, 1.0);
+    let mut rng = Rng::new(settings.seed);
+    // Intensities and related conditions share the effect of their base type.
+    match weather.family() {
+        WeatherType::Drizzle | WeatherType::Rain => {
+            darken(image, 1.0 - 0.2 * intensity);
+            rain(image, intensity, &mut rng);
+        }
+        WeatherType::Thunder => {
+            darken(image, 1.0 - 0.35 * intensity);
+            rain(image, intensity, &mut rng);
+        }
+        WeatherType::Snow => snow(image, intensity, &mut rng),
+        WeatherType::Mist => haze(image, 0.6 * intensity),
+        WeatherType::BrokenClouds => darken(image, 1.0 - 0.08 * settings.strength),
+        WeatherType::OvercastClouds => darken(image, 1.0 - 0.15 * settings.strength),
+        _ => return false,
+    }
+    true
+}
+
+fn darken(image: &mut RgbImage, factor: f32) {
+    for value in image.iter_mut() {
+        *value = (*value as f32 * factor.clamp(0.0, 1.0)).round() as u8;
+    }
+}
+
+// Mix a colour into a single pixel, ignoring coordinates outside the image.
+pub fn mix(image: &mut RgbImage, x: i64, y: i64, colour: [u8; 3], alpha: f32) {
+    if x < 0 || y < 0 || x >= image.width() as i64 || y >= image.height() as i64 {
+        return;
+    }
+    let pixel = image.get_pixel_mut(x as u32, y as u32);
+    for (value, target) in pixel.0.iter_mut().zip(colour) {
+        *value = (*value as f32 + (target as f32 - *value as f32) * alpha).round() as u8;
+    }
+}
+
+// Slanted light streaks, length scales with the image height.
+fn rain(image: &mut RgbImage, intensity: f32, rng: &mut Rng) {
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let count = (width * height / 1500.0 * intensity) as usize;
+    let scale = height / 1080.0;
+    for _ in 0..count {
+        let x = rng.next_f32() * width;
+        let y = rng.next_f32() * height;
+        let length = (12.0 + rng.next_f32() * 24.0) * scale.max(0.1);
+        let alpha = 0.15 + 0.2 * rng.next_f32();
+        for step in 0..length.ceil() as i64 {
+            let offset = step as f32;
+            mix(
+                image,
+                (x + offset * 0.2) as i64,
+                (y + offset) as i64,
+                [210, 215, 225],
+                alpha,
+            );
+        }
+    }
+}
+
+// Round white flakes of varying size.
+fn snow(image: &mut RgbImage, intensity: f32, rng: &mut Rng) {
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let count = (width * height / 2500.0 * intensity) as usize;
+    let scale = (height / 1080.0).max(0.1);
+    for _ in 0..count {
+        let x = (rng.next_f32() * width) as i64;
+        let y = (rng.next_f32() * height) as i64;
+        let radius = ((1.0 + rng.next_f32() * 2.5) * scale).round().max(1.0) as i64;
+        let alpha = 0.5 + 0.4 * rng.next_f32();
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                if dx * dx + dy * dy <= radius * radius {
+                    mix(image, x + dx, y + dy, [250, 250, 255], alpha);
+                }
+            }
+        }
+    }
+}
+
+// Grey haze, denser towards the bottom of the image.
+fn haze(image: &mut RgbImage, density: f32) {
+    let height = image.height().max(1) as f32;
+    for (_, y, pixel) in image.enumerate_pixels_mut() {
+        let alpha = density * (0.5 + 0.5 * y as f32 / height);
+        for (value, target) in pixel.0.iter_mut().zip([200u8, 202, 208]) {
+            *value = (*value as f32 + (target as f32 - *value as f32) * alpha).round() as u8;
+        }
+    }
+}
+
+// Render the weather effect onto an image and write it to the cache. Returns None if the
+// weather has no effect, so the original image can be used.
+pub fn render_cached(
+    image: &str,
+    size: Option<(u32, u32)>,
+    weather: &WeatherType,
+    precipitation: f64,
+    settings: &EffectSettings,
+    cache_dir: &Path,
+) -> Result<Option<String>> {
+    let mut hasher = DefaultHasher::new();
+    image.hash(&mut hasher);
+    utils::modified_secs(Path::new(image)).hash(&mut hasher);
+    (size, weather.to_string(), settings.seed).hash(&mut hasher);
+    precipitation.to_bits().hash(&mut hasher);
+    settings.strength.to_bits().hash(&mut hasher);
+    let file = cache_dir.join(format!("{:016x}.jpg", hasher.finish()));
+    if file.is_file() {
+        return Ok(Some(file.display().to_string()));
+    }
+
+    let size = match size {
+        Some(size) => size,
+        None => image::image_dimensions(image)?,
+    };
+    let mut canvas = transition::load_fitted(Path::new(image), size)?;
+    if !render(&mut canvas, weather, precipitation, settings) {
+        return Ok(None);
+    }
+    fs::create_dir_all(cache_dir)?;
+    canvas.save(&file)?;
+    Ok(Some(file.display().to_string()))
+}
+
+// Remove rendered images that are no longer in use.
+pub fn prune(cache_dir: &Path, keep: &[String]) {
+    if let Ok(entries) = fs::read_dir(cache_dir) {
+        for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+            if !keep.contains(&path.display().to_string()) {
+                fs::remove_file(path).ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    // FNV-1a over the raw pixels, compact stand-in for a stored golden image.
+    fn checksum(image: &RgbImage) -> u64 {
+        image
+            .as_raw()
+            .iter()
+            .fold(0xcbf29ce484222325, |hash, byte| {
+                (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+            })
+    }
+
+    fn rendered(weather: WeatherType, seed: u64) -> RgbImage {
+        let mut image = RgbImage::from_pixel(96, 64, Rgb([60, 90, 120]));
+        let settings = EffectSettings {
+            enabled: true,
+            seed,
+            strength: 1.0,
+        };
+        render(&mut image, &weather, 2.0, &settings);
+        image
+    }
+
+    #[test]
+    fn test_render_is_deterministic() {
+        for weather in [WeatherType::Rain, WeatherType::Snow, WeatherType::Thunder] {
+            assert_eq!(rendered(weather.clone(), 7), rendered(weather.clone(), 7));
+            assert_ne!(rendered(weather.clone(), 7), rendered(weather, 8));
+        }
+    }
+
+    #[test]
+    fn test_render_golden() {
+        assert_eq!(checksum(&rendered(WeatherType::Rain, 42)), RAIN_GOLDEN);
+        assert_eq!(checksum(&rendered(WeatherType::Snow, 42)), SNOW_GOLDEN);
+    }
+
+    #[test]
+    fn test_render_uniform_effects() {
+        let clear = RgbImage::from_pixel(96, 64, Rgb([60, 90, 120]));
+        assert_eq!(rendered(WeatherType::Clear, 1), clear);
+        assert_eq!(
+            rendered(WeatherType::OvercastClouds, 1).get_pixel(0, 0),
+            &Rgb([51, 77, 102])
+        );
+        // Haze is denser at the bottom.
+        let mist = rendered(WeatherType::Mist, 1);
+        assert!(mist.get_pixel(0, 63)[0] > mist.get_pixel(0, 0)[0]);
+    }
+
+    const RAIN_GOLDEN: u64 = 7425850189630945275;
+    const SNOW_GOLDEN: u64 = 14262767583376481184;
+}
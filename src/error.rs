@@ -0,0 +1,131 @@
+use crate::secret::ApiKey;
+use crate::types::backend::Backend;
+use std::path::PathBuf;
+use thiserror::Error;
+
+// Classes of failures that need a different reaction. They are attached to errors as
+// anyhow context, so the underlying cause stays in the chain.
+#[derive(Debug, Error)]
+pub enum FehtherError {
+    #[error("invalid configuration in {}", .0.display())]
+    Config(PathBuf),
+    #[error("the weather provider rejected the API key: {0}")]
+    ProviderAuth(String),
+    #[error("the weather provider rate limit was reached: {0}")]
+    ProviderQuota(String),
+    #[error("the weather provider rejected the request: {0}")]
+    ProviderRequest(String),
+    #[error("could not reach {0}")]
+    Network(String),
+    #[error("unexpected response from {0}")]
+    Parse(String),
+    #[error("could not access {}", .0.display())]
+    Filesystem(PathBuf),
+    #[error("{0} could not set the wallpaper")]
+    Setter(Backend),
+}
+
+impl FehtherError {
+    // Exit code of the process, following sysexits.h.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FehtherError::Config(_) => 78,
+            FehtherError::ProviderAuth(_) => 77,
+            FehtherError::ProviderQuota(_) => 75,
+            FehtherError::ProviderRequest(_) => 76,
+            FehtherError::Network(_) => 69,
+            FehtherError::Parse(_) => 65,
+            FehtherError::Filesystem(_) => 74,
+            FehtherError::Setter(_) => 70,
+        }
+    }
+
+    // What the user can do about it.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            FehtherError::Config(_) => "check config.ini, the README lists all options",
+            FehtherError::ProviderAuth(_) => {
+                "check the API key in [settings] or the token in [home-assistant], new OpenWeatherMap keys can take a few hours to activate"
+            }
+            FehtherError::ProviderQuota(_) => {
+                "too many requests for this API key, fehther waits before asking again"
+            }
+            FehtherError::ProviderRequest(_) => {
+                "check the location in [settings], `fehther locate <place>` finds the right settings"
+            }
+            FehtherError::Network(_) => {
+                "check the network connection, fehther retries on the next poll"
+            }
+            FehtherError::Parse(_) => "the provider may have changed its API, please report this",
+            FehtherError::Filesystem(_) => "check that the path exists and is writable",
+            FehtherError::Setter(_) => {
+                "check that the backend is installed and a display is available"
+            }
+        }
+    }
+}
+
+// Find the class of an error anywhere in its chain, None for unexpected failures.
+pub fn classify(error: &anyhow::Error) -> Option<&FehtherError> {
+    error.downcast_ref::<FehtherError>()
+}
+
+// Error message for the log, without the API key and with a hint when the class is known.
+pub fn describe(error: &anyhow::Error, key: &ApiKey) -> String {
+    let message = key.redact(&format!("{:#}", error));
+    match classify(error) {
+        Some(class) => format!("{}\n  hint: {}", message, class.hint()),
+        None => message,
+    }
+}
+
+// Exit code for an error, 1 when it has no class.
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    classify(error).map_or(1, FehtherError::exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context, anyhow};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_classify() {
+        // The class is found below other context and keeps the cause in the message.
+        let error = Err::<(), _>(anyhow!("connection refused"))
+            .context(FehtherError::Network("api.openweathermap.org".to_string()))
+            .context("weather request failed")
+            .unwrap_err();
+        assert!(matches!(classify(&error), Some(FehtherError::Network(_))));
+        assert_eq!(exit_code(&error), 69);
+        assert_eq!(
+            format!("{:#}", error),
+            "weather request failed: could not reach api.openweathermap.org: connection refused"
+        );
+        assert_eq!(
+            describe(&error, &ApiKey::new("refused")),
+            "weather request failed: could not reach api.openweathermap.org: connection [redacted]\n  \
+             hint: check the network connection, fehther retries on the next poll"
+        );
+        assert!(classify(&anyhow!("unexpected")).is_none());
+        assert_eq!(exit_code(&anyhow!("unexpected")), 1);
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = [
+            FehtherError::Config(PathBuf::new()),
+            FehtherError::ProviderAuth(String::new()),
+            FehtherError::ProviderQuota(String::new()),
+            FehtherError::ProviderRequest(String::new()),
+            FehtherError::Network(String::new()),
+            FehtherError::Parse(String::new()),
+            FehtherError::Filesystem(PathBuf::new()),
+            FehtherError::Setter(Backend::Feh),
+        ];
+        let codes: HashSet<i32> = errors.iter().map(FehtherError::exit_code).collect();
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&1));
+    }
+}
@@ -1,36 +1,471 @@
 #![allow(dead_code)]
+use crate::error::FehtherError;
+use crate::http::HttpClient;
+use crate::location::Location;
+use crate::secret::ApiKey;
+use crate::types::weathertype::WeatherType;
+use anyhow::{Context, Result, anyhow};
+use reqwest::{StatusCode, Url};
 use serde::Deserialize;
+use serde::de::DeserializeOwned;
 
 #[derive(Deserialize, Debug)]
 pub struct WeatherResponse {
+    // Empty or missing in some partial responses.
+    #[serde(default)]
     pub weather: Vec<Weather>,
+    #[serde(default)]
     pub sys: Sys,
+    #[serde(default)]
+    pub main: Option<Main>,
+    // City name as known to the provider.
+    #[serde(default)]
+    pub name: String,
+    // Offset from UTC in seconds at the observed location.
+    #[serde(default)]
+    pub timezone: i32,
+    #[serde(default)]
+    pub wind: Option<Wind>,
+    #[serde(default)]
+    pub clouds: Option<Clouds>,
+    // Visibility in metres.
+    #[serde(default)]
+    pub visibility: Option<f64>,
+    #[serde(default)]
+    pub coord: Option<Coord>,
+    #[serde(default)]
+    pub rain: Option<Precipitation>,
+    #[serde(default)]
+    pub snow: Option<Precipitation>,
+}
+
+// Precipitation volume in mm over the last hour.
+#[derive(Deserialize, Debug)]
+pub struct Precipitation {
+    #[serde(rename = "1h", default)]
+    pub one_hour: f64,
+}
+
+// Temperatures in degrees Celsius, humidity in percent and pressure in hPa.
+#[derive(Deserialize, Debug)]
+pub struct Main {
+    pub temp: f64,
+    pub feels_like: f64,
+    pub temp_min: f64,
+    pub temp_max: f64,
+    pub pressure: f64,
+    pub humidity: f64,
+}
+
+// Wind speed and gusts in metres per second.
+#[derive(Deserialize, Debug)]
+pub struct Wind {
+    pub speed: f64,
+    #[serde(default)]
+    pub gust: Option<f64>,
+}
+
+// Cloud cover in percent.
+#[derive(Deserialize, Debug)]
+pub struct Clouds {
+    pub all: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Coord {
+    pub lat: f64,
+    pub lon: f64,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct Weather {
     pub id: i32,
     pub main: String,
+    #[serde(default)]
+    pub description: String,
 }
 
-#[derive(Deserialize, Debug)]
+// Sunrise and sunset are missing near the poles during polar day and night.
+#[derive(Deserialize, Debug, Default)]
 pub struct Sys {
-    pub sunrise: i32,
-    pub sunset: i32,
+    #[serde(default)]
+    pub sunrise: Option<i32>,
+    #[serde(default)]
+    pub sunset: Option<i32>,
+    #[serde(default)]
+    pub country: Option<String>,
+}
+
+// Current weather as reported by a weather source. Drives the modes, is shown in the text
+// overlay and compared by weather rules, and is kept between polls so the clock can be
+// redrawn. Values the source did not report are None.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Observation {
+    pub weather: Option<WeatherType>,
+    pub description: String,
+    pub temperature: Option<f64>,
+    pub feels_like: Option<f64>,
+    pub humidity: Option<f64>,
+    pub pressure: Option<f64>,
+    pub wind_speed: Option<f64>,
+    pub wind_gust: Option<f64>,
+    pub clouds: Option<f64>,
+    pub visibility: Option<f64>,
+    // Precipitation in mm over the last hour, zero when none was reported.
+    pub rain: f64,
+    pub snow: f64,
+    pub location: String,
+    pub sunrise: Option<i64>,
+    pub sunset: Option<i64>,
+    pub timezone: i64,
+    pub coordinates: Option<(f64, f64)>,
+}
+
+impl WeatherResponse {
+    // Weather type of the first reported condition, None when no condition was reported.
+    pub fn condition(&self) -> Option<WeatherType> {
+        self.weather
+            .first()
+            .map(|weather| WeatherType::condition(weather.id))
+    }
+
+    // Sunrise and sunset, None unless both were reported.
+    pub fn sun_times(&self) -> Option<(i32, i32)> {
+        Some((self.sys.sunrise?, self.sys.sunset?))
+    }
+
+    pub fn observation(&self) -> Observation {
+        let location = match &self.sys.country {
+            Some(country) if !self.name.is_empty() && !country.is_empty() => {
+                format!("{}, {}", self.name, country)
+            }
+            _ => self.name.clone(),
+        };
+        Observation {
+            weather: self.condition(),
+            description: self
+                .weather
+                .first()
+                .map(|weather| weather.description.clone())
+                .unwrap_or_default(),
+            temperature: self.main.as_ref().map(|main| main.temp),
+            feels_like: self.main.as_ref().map(|main| main.feels_like),
+            humidity: self.main.as_ref().map(|main| main.humidity),
+            pressure: self.main.as_ref().map(|main| main.pressure),
+            wind_speed: self.wind.as_ref().map(|wind| wind.speed),
+            wind_gust: self.wind.as_ref().and_then(|wind| wind.gust),
+            clouds: self.clouds.as_ref().map(|clouds| clouds.all),
+            visibility: self.visibility,
+            rain: self.rain.as_ref().map_or(0.0, |rain| rain.one_hour),
+            snow: self.snow.as_ref().map_or(0.0, |snow| snow.one_hour),
+            location,
+            sunrise: self.sys.sunrise.map(i64::from),
+            sunset: self.sys.sunset.map(i64::from),
+            timezone: self.timezone as i64,
+            coordinates: self.coord.as_ref().map(|coord| (coord.lat, coord.lon)),
+        }
+    }
+}
+
+// Response of the 5 day forecast endpoint, one entry every three hours.
+#[derive(Deserialize, Debug)]
+pub struct ForecastResponse {
+    pub list: Vec<ForecastEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ForecastEntry {
+    // Start of the forecast period, unix time.
+    pub dt: i64,
+    pub weather: Vec<Weather>,
+}
+
+impl ForecastResponse {
+    // Forecast weather types with the start of their period.
+    pub fn entries(&self) -> Vec<(i64, WeatherType)> {
+        self.list
+            .iter()
+            .filter_map(|entry| {
+                let weather = entry.weather.first()?;
+                Some((entry.dt, WeatherType::condition(weather.id)))
+            })
+            .collect()
+    }
+}
+
+// Place found by the geocoding API.
+#[derive(Deserialize, Debug)]
+pub struct GeocodingMatch {
+    pub name: String,
+    #[serde(default)]
+    pub state: Option<String>,
+    pub country: String,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+// Build an OpenWeatherMap API url below the base url, query values are URL-encoded.
+pub fn api_url(
+    base_url: &Url,
+    endpoint: &str,
+    params: &[(&str, String)],
+    key: &ApiKey,
+) -> Result<Url> {
+    let mut url = base_url.join(endpoint)?;
+    url.query_pairs_mut()
+        .extend_pairs(params)
+        .append_pair("appid", key.expose());
+    Ok(url)
+}
+
+// Error body of the provider, e.g. {"cod": 401, "message": "Invalid API key..."}. The code
+// is a number or a string depending on the endpoint.
+#[derive(Deserialize, Debug, Default)]
+struct ErrorPayload {
+    #[serde(default)]
+    cod: serde_json::Value,
+    #[serde(default)]
+    message: String,
+}
+
+impl ErrorPayload {
+    fn code(&self) -> Option<u16> {
+        match &self.cod {
+            serde_json::Value::Number(code) => code.as_u64()?.try_into().ok(),
+            serde_json::Value::String(code) => code.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+// Turn an error status or error payload into an error of its class. Some endpoints answer
+// 200 with the error code in the body.
+pub fn check_status(status: StatusCode, body: &str, host: &str) -> Result<()> {
+    let payload: ErrorPayload = serde_json::from_str(body).unwrap_or_default();
+    let code = match status.is_success() {
+        true => payload.code(),
+        false => Some(status.as_u16()),
+    };
+    let Some(code) = code.filter(|code| *code >= 400) else {
+        return Ok(());
+    };
+    let message = match payload.message.is_empty() {
+        true => StatusCode::from_u16(code).map_or(code.to_string(), |code| code.to_string()),
+        false => format!("{} {}", code, payload.message),
+    };
+    Err(match code {
+        401 => FehtherError::ProviderAuth(message).into(),
+        429 => FehtherError::ProviderQuota(message).into(),
+        400..500 => FehtherError::ProviderRequest(message).into(),
+        // Server errors usually pass, they are retried like network errors.
+        _ => anyhow!(message).context(FehtherError::Network(host.to_string())),
+    })
+}
+
+// Send a request and parse the response, leaving the url with the API key out of errors.
+async fn request<T: DeserializeOwned>(http: &HttpClient, url: Url) -> Result<T> {
+    let host = url.host_str().unwrap_or_default().to_string();
+    let endpoint = url.path().trim_start_matches('/').to_string();
+    let response = http
+        .client
+        .get(url)
+        .send()
+        .await
+        .map_err(|error| error.without_url())
+        .with_context(|| FehtherError::Network(host.clone()))?;
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|error| error.without_url())
+        .with_context(|| FehtherError::Network(host.clone()))?;
+    check_status(status, &body, &host)?;
+    serde_json::from_str(&body).with_context(|| FehtherError::Parse(endpoint))
 }
 
 // Get API response from openweathermap
 pub async fn openweathermap(
-    key: &str,
-    city: &str,
-    country_code: &str,
-) -> Result<WeatherResponse, anyhow::Error> {
-    let body = reqwest::get(format!(
-        "https://api.openweathermap.org/data/2.5/weather?q={},{}&units=metric&appid={}",
-        city, country_code, key
-    ))
-    .await?
-    .text()
-    .await?;
-    Ok(serde_json::from_str(&body)?)
+    http: &HttpClient,
+    key: &ApiKey,
+    location: &Location,
+) -> Result<WeatherResponse> {
+    let mut params = location.query()?;
+    params.push(("units", "metric".to_string()));
+    request(
+        http,
+        api_url(&http.base_url, "data/2.5/weather", &params, key)?,
+    )
+    .await
+}
+
+// Get forecast from openweathermap
+pub async fn openweathermap_forecast(
+    http: &HttpClient,
+    key: &ApiKey,
+    location: &Location,
+) -> Result<ForecastResponse> {
+    let mut params = location.query()?;
+    params.push(("units", "metric".to_string()));
+    request(
+        http,
+        api_url(&http.base_url, "data/2.5/forecast", &params, key)?,
+    )
+    .await
+}
+
+// Find places matching a free text query, e.g. "Springfield, US".
+pub async fn geocode(http: &HttpClient, key: &ApiKey, query: &str) -> Result<Vec<GeocodingMatch>> {
+    let params = [("q", query.to_string()), ("limit", "5".to_string())];
+    request(
+        http,
+        api_url(&http.base_url, "geo/1.0/direct", &params, key)?,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error;
+    use crate::http::HttpSettings;
+    use crate::test_server::serve;
+
+    // Client for a stub answering on the given base url.
+    fn stub(base_url: String) -> HttpClient {
+        HttpClient::new(&HttpSettings {
+            base_url,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    async fn weather(status: &'static str, body: &'static str) -> Result<WeatherResponse> {
+        let location = Location::CityId(2759794);
+        openweathermap(&stub(serve(status, body)), &ApiKey::new("key"), &location).await
+    }
+
+    fn class(error: &anyhow::Error) -> &FehtherError {
+        error::classify(error).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_weather_response() {
+        let response = weather("200 OK", include_str!("../test_fixtures/weather.json"))
+            .await
+            .unwrap();
+        assert_eq!(response.condition(), Some(WeatherType::LightRain));
+        assert_eq!(response.sun_times(), Some((1747021974, 1747077771)));
+        let observation = response.observation();
+        assert_eq!(observation.location, "Amsterdam, NL");
+        assert_eq!(observation.temperature, Some(11.3));
+        assert_eq!(observation.rain, 0.42);
+    }
+
+    #[tokio::test]
+    async fn test_partial_response() {
+        let response = weather(
+            "200 OK",
+            include_str!("../test_fixtures/weather_partial.json"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.condition(), None);
+        assert_eq!(response.sun_times(), None);
+        let observation = response.observation();
+        assert_eq!(observation.location, "Longyearbyen");
+        assert_eq!(observation.sunrise, None);
+    }
+
+    #[tokio::test]
+    async fn test_error_responses() {
+        let error = weather(
+            "401 Unauthorized",
+            include_str!("../test_fixtures/weather_401.json"),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(class(&error), FehtherError::ProviderAuth(_)));
+        assert!(
+            error.to_string().contains("401 Invalid API key"),
+            "{}",
+            error
+        );
+
+        let error = weather(
+            "404 Not Found",
+            include_str!("../test_fixtures/weather_404.json"),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(class(&error), FehtherError::ProviderRequest(_)));
+        assert!(
+            error.to_string().contains("404 city not found"),
+            "{}",
+            error
+        );
+
+        let error = weather(
+            "429 Too Many Requests",
+            include_str!("../test_fixtures/weather_429.json"),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(class(&error), FehtherError::ProviderQuota(_)));
+
+        // An error status without a payload still has its class.
+        let error = weather("502 Bad Gateway", "<html>Bad Gateway</html>")
+            .await
+            .unwrap_err();
+        assert!(matches!(class(&error), FehtherError::Network(_)));
+        assert!(
+            format!("{:#}", error).contains("502 Bad Gateway"),
+            "{:#}",
+            error
+        );
+
+        // Error code in the body of a 200 response.
+        let error = weather("200 OK", r#"{"cod":"404","message":"city not found"}"#)
+            .await
+            .unwrap_err();
+        assert!(matches!(class(&error), FehtherError::ProviderRequest(_)));
+
+        let error = weather(
+            "200 OK",
+            include_str!("../test_fixtures/weather_malformed.json"),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(class(&error), FehtherError::Parse(_)));
+        assert_eq!(
+            error.to_string(),
+            "unexpected response from data/2.5/weather"
+        );
+
+        // Nothing listens on port 1.
+        let location = Location::CityId(2759794);
+        let http = stub("http://127.0.0.1:1".to_string());
+        let error = openweathermap(&http, &ApiKey::new("key"), &location)
+            .await
+            .unwrap_err();
+        assert!(matches!(class(&error), FehtherError::Network(_)));
+    }
+
+    #[test]
+    fn test_api_url() {
+        let location = Location::City {
+            city: "São Paulo".to_string(),
+            country: "BR".to_string(),
+        };
+        let url = api_url(
+            &HttpClient::new(&HttpSettings::default()).unwrap().base_url,
+            "data/2.5/weather",
+            &location.query().unwrap(),
+            &ApiKey::new("k&y"),
+        )
+        .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://api.openweathermap.org/data/2.5/weather?q=S%C3%A3o+Paulo%2CBR&appid=k%26y"
+        );
+    }
 }
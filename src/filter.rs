@@ -0,0 +1,348 @@
+use crate::library::{self, Library, Record};
+use crate::types::orientation::Orientation;
+use crate::utils;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Dimensions read from an image header.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ImageInfo {
+    pub fn aspect_ratio(&self) -> f64 {
+        self.width as f64 / self.height.max(1) as f64
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        Orientation::from_dimensions(self.width, self.height)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CacheEntry {
+    modified: u64,
+    // None if the image header could not be decoded.
+    info: Option<ImageInfo>,
+}
+
+// Image header results, keyed by path and invalidated when the modification time changes.
+// Used in place of the library when its database cannot be opened.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct InfoCache {
+    entries: HashMap<String, CacheEntry>,
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl InfoCache {
+    pub fn cache_file() -> PathBuf {
+        utils::fetch_cache_dir().join("image-info.json")
+    }
+
+    // Load cache from disk, a missing or unreadable cache starts empty.
+    pub fn load(cache_file: &Path) -> InfoCache {
+        fs::read_to_string(cache_file)
+            .ok()
+            .and_then(|body| serde_json::from_str(&body).ok())
+            .unwrap_or_default()
+    }
+
+    // Only writes the cache if new images were inspected.
+    pub fn save(&self, cache_file: &Path) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        if let Some(parent) = cache_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(cache_file, serde_json::to_string(self)?)
+            .with_context(|| format!("Failed to write image cache: {}", cache_file.display()))
+    }
+
+    // Read image dimensions from the header, or from cache if the image is unchanged.
+    pub fn inspect(&mut self, image: &str) -> Option<ImageInfo> {
+        let modified = utils::modified_secs(Path::new(image));
+        if let Some(entry) = self.entries.get(image)
+            && entry.modified == modified
+        {
+            return entry.info;
+        }
+        let info = read_header(Path::new(image));
+        self.entries
+            .insert(image.to_string(), CacheEntry { modified, info });
+        self.changed = true;
+        info
+    }
+}
+
+// Read image dimensions from the header, None if the image cannot be decoded.
+pub fn read_header(image: &Path) -> Option<ImageInfo> {
+    image::ImageReader::open(image)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+        .map(|(width, height)| ImageInfo { width, height })
+}
+
+// Rules candidate images must pass before they are handed to feh.
+#[derive(Debug, Clone, Default)]
+pub struct ImageFilter {
+    pub min_width: Option<u32>,
+    pub min_height: Option<u32>,
+    pub aspect: Option<Orientation>,
+    // Require the image to cover at least one connected output, with a similar aspect ratio.
+    pub match_output: bool,
+    // Allowed relative difference between image and output aspect ratio.
+    pub aspect_tolerance: f64,
+}
+
+impl ImageFilter {
+    // Filtering needs image headers, skip it entirely when no rules are configured.
+    pub fn is_active(&self) -> bool {
+        self.min_width.is_some()
+            || self.min_height.is_some()
+            || self.aspect.is_some()
+            || self.match_output
+    }
+
+    pub fn accepts(&self, info: &ImageInfo, outputs: &[(u32, u32)]) -> bool {
+        self.min_width.is_none_or(|width| info.width >= width)
+            && self.min_height.is_none_or(|height| info.height >= height)
+            && self
+                .aspect
+                .is_none_or(|aspect| info.orientation() == aspect)
+            && (!self.match_output || outputs.is_empty() || self.fits_output(info, outputs))
+    }
+
+    fn fits_output(&self, info: &ImageInfo, outputs: &[(u32, u32)]) -> bool {
+        outputs.iter().any(|&output| self.fits(info, output))
+    }
+
+    fn fits(&self, info: &ImageInfo, (width, height): (u32, u32)) -> bool {
+        let output_ratio = width as f64 / height.max(1) as f64;
+        info.width >= width
+            && info.height >= height
+            && (info.aspect_ratio() - output_ratio).abs() / output_ratio <= self.aspect_tolerance
+    }
+
+    // Drop corrupt images and images that break the configured rules. Image headers are
+    // read once and stored in the library.
+    pub fn apply(&self, records: Vec<Record>, library: &Library) -> Vec<Record> {
+        let outputs = self.fetch_outputs();
+        records
+            .into_iter()
+            .filter_map(|mut record| {
+                record.info = library.inspect(&record);
+                record.inspected = true;
+                let info = record.info?;
+                self.accepts(&info, &outputs).then_some(record)
+            })
+            .collect()
+    }
+
+    // Same as apply, for images found by a plain folder scan.
+    pub fn apply_cached(&self, images: Vec<String>, cache: &mut InfoCache) -> Vec<Record> {
+        let outputs = self.fetch_outputs();
+        images
+            .into_iter()
+            .filter_map(|image| {
+                let info = cache.inspect(&image)?;
+                self.accepts(&info, &outputs).then(|| Record {
+                    info: Some(info),
+                    inspected: true,
+                    ..Record::new(image)
+                })
+            })
+            .collect()
+    }
+
+    // Pick one image per output. With output matching on, each output only gets an image
+    // that fits it, outputs without a fitting image are left to the backend.
+    pub fn select(&self, mut records: Vec<Record>, outputs: &[(u32, u32)]) -> Vec<String> {
+        if !self.match_output || outputs.is_empty() {
+            return library::select(records, outputs.len().max(1));
+        }
+        let mut selected = Vec::new();
+        for &output in outputs {
+            let fitting = records
+                .iter()
+                .filter(|record| record.info.is_some_and(|info| self.fits(&info, output)))
+                .cloned()
+                .collect();
+            let Some(image) = library::select(fitting, 1).pop() else {
+                break;
+            };
+            records.retain(|record| record.path != image);
+            selected.push(image);
+        }
+        selected
+    }
+
+    // Outputs are only needed to match images against them.
+    fn fetch_outputs(&self) -> Vec<(u32, u32)> {
+        if self.match_output {
+            utils::fetch_outputs()
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_rules() {
+        let wide = ImageInfo {
+            width: 3840,
+            height: 2160,
+        };
+        let thumbnail = ImageInfo {
+            width: 640,
+            height: 480,
+        };
+        let portrait = ImageInfo {
+            width: 2160,
+            height: 3840,
+        };
+        let filter = ImageFilter {
+            min_width: Some(1920),
+            aspect: Some(Orientation::Landscape),
+            ..Default::default()
+        };
+        assert!(filter.accepts(&wide, &[]));
+        assert!(!filter.accepts(&thumbnail, &[]));
+        assert!(!filter.accepts(&portrait, &[]));
+
+        let filter = ImageFilter {
+            match_output: true,
+            aspect_tolerance: 0.1,
+            ..Default::default()
+        };
+        assert!(filter.accepts(&wide, &[(1920, 1080)]));
+        assert!(!filter.accepts(&wide, &[(1920, 1200), (1080, 1920)]));
+        assert!(filter.accepts(&portrait, &[(1920, 1200), (1080, 1920)]));
+        assert!(!filter.accepts(&thumbnail, &[(1920, 1080)]));
+    }
+
+    #[test]
+    fn test_select_per_output() {
+        let record = |path: &str, width, height| Record {
+            info: Some(ImageInfo { width, height }),
+            inspected: true,
+            ..Record::new(path.to_string())
+        };
+        let records = vec![
+            record("tall.jpg", 1080, 1920),
+            record("wide.jpg", 3840, 2160),
+        ];
+        let filter = ImageFilter {
+            match_output: true,
+            aspect_tolerance: 0.1,
+            ..Default::default()
+        };
+        // Both images pass the filter, but only one of them fits each output.
+        for _ in 0..10 {
+            assert_eq!(
+                filter.select(records.clone(), &[(3840, 2160), (1080, 1920)]),
+                vec!["wide.jpg", "tall.jpg"]
+            );
+            assert_eq!(
+                filter.select(records.clone(), &[(1080, 1920), (3840, 2160)]),
+                vec!["tall.jpg", "wide.jpg"]
+            );
+        }
+        assert_eq!(filter.select(records.clone(), &[(1920, 1200)]).len(), 0);
+        let filter = ImageFilter::default();
+        assert_eq!(
+            filter.select(records, &[(1920, 1080), (1920, 1080)]).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_read_header_and_apply() {
+        let dir = tempfile::tempdir().unwrap();
+        image::RgbImage::new(32, 16)
+            .save(dir.path().join("wide.png"))
+            .unwrap();
+        image::RgbImage::new(16, 32)
+            .save(dir.path().join("tall.png"))
+            .unwrap();
+        fs::write(dir.path().join("corrupt.jpg"), b"not an image").unwrap();
+
+        assert_eq!(
+            read_header(&dir.path().join("wide.png")),
+            Some(ImageInfo {
+                width: 32,
+                height: 16
+            })
+        );
+        assert_eq!(read_header(&dir.path().join("corrupt.jpg")), None);
+
+        let library = Library::open_in_memory().unwrap();
+        library
+            .rescan(dir.path(), &crate::scan::ScanOptions::default())
+            .unwrap();
+        let filter = ImageFilter {
+            aspect: Some(Orientation::Landscape),
+            ..Default::default()
+        };
+        let accepted = filter.apply(library.images().unwrap(), &library);
+        assert_eq!(accepted.len(), 1);
+        assert!(accepted[0].path.ends_with("wide.png"));
+        // Dimensions are stored after the first inspection.
+        assert!(
+            library
+                .images()
+                .unwrap()
+                .iter()
+                .all(|record| record.inspected)
+        );
+    }
+
+    #[test]
+    fn test_inspect_and_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let valid = dir.path().join("valid.png");
+        let corrupt = dir.path().join("corrupt.jpg");
+        image::RgbImage::new(32, 16).save(&valid).unwrap();
+        fs::write(&corrupt, b"not an image").unwrap();
+        let (valid, corrupt) = (valid.display().to_string(), corrupt.display().to_string());
+
+        let mut cache = InfoCache::default();
+        let info = cache.inspect(&valid);
+        assert_eq!(
+            info,
+            Some(ImageInfo {
+                width: 32,
+                height: 16
+            })
+        );
+        assert_eq!(cache.inspect(&corrupt), None);
+
+        let cache_file = dir.path().join("cache.json");
+        cache.save(&cache_file).unwrap();
+        let mut cache = InfoCache::load(&cache_file);
+        assert_eq!(cache.inspect(&valid), info);
+        assert!(!cache.changed);
+
+        let filter = ImageFilter {
+            aspect: Some(Orientation::Landscape),
+            ..Default::default()
+        };
+        let accepted = filter.apply_cached(vec![valid.clone(), corrupt], &mut cache);
+        assert_eq!(accepted.len(), 1);
+        assert_eq!(accepted[0].path, valid);
+        assert_eq!(accepted[0].info, info);
+    }
+}
@@ -0,0 +1,143 @@
+use crate::types::forecastpolicy::ForecastPolicy;
+use crate::types::weathertype::WeatherType;
+
+// Length of a forecast period of the OpenWeatherMap forecast endpoint.
+const PERIOD: i64 = 3 * 3600;
+
+// Combine the current weather with the forecast periods that overlap the next
+// `horizon` hours. Falls back to the current weather without forecast data.
+pub fn aggregate(
+    current: &WeatherType,
+    forecast: &[(i64, WeatherType)],
+    now: i64,
+    horizon: u32,
+    policy: ForecastPolicy,
+) -> WeatherType {
+    let end = now + horizon as i64 * 3600;
+    let upcoming: Vec<&(i64, WeatherType)> = forecast
+        .iter()
+        .filter(|(start, _)| *start < end && start + PERIOD > now)
+        .collect();
+    match policy {
+        // Earliest of the most severe, so the current weather wins ties.
+        ForecastPolicy::Worst => upcoming
+            .iter()
+            .map(|(_, weather)| weather)
+            .fold(current, |worst, weather| {
+                match weather.severity() > worst.severity() {
+                    true => weather,
+                    false => worst,
+                }
+            })
+            .clone(),
+        // Most frequent weather including the current one, ties go to the earliest.
+        ForecastPolicy::Majority => {
+            let candidates: Vec<&WeatherType> = std::iter::once(current)
+                .chain(upcoming.iter().map(|(_, weather)| weather))
+                .collect();
+            let count =
+                |weather: &WeatherType| candidates.iter().filter(|w| **w == weather).count();
+            candidates
+                .iter()
+                .fold(current, |majority, weather| {
+                    match count(weather) > count(majority) {
+                        true => weather,
+                        false => majority,
+                    }
+                })
+                .clone()
+        }
+        // Period covering an hour from now.
+        ForecastPolicy::NextHour => upcoming
+            .iter()
+            .find(|(start, _)| (*start..start + PERIOD).contains(&(now + 3600)))
+            .map_or(current, |(_, weather)| weather)
+            .clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    // Periods starting half an hour ago, then every three hours.
+    fn forecast(types: &[WeatherType]) -> Vec<(i64, WeatherType)> {
+        types
+            .iter()
+            .enumerate()
+            .map(|(i, weather)| (NOW - 1800 + i as i64 * PERIOD, weather.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_worst() {
+        let forecast = forecast(&[
+            WeatherType::FewClouds,
+            WeatherType::Rain,
+            WeatherType::Thunder,
+        ]);
+        let aggregate = |horizon| {
+            aggregate(
+                &WeatherType::Clear,
+                &forecast,
+                NOW,
+                horizon,
+                ForecastPolicy::Worst,
+            )
+        };
+        // The period that has already started counts as upcoming.
+        assert_eq!(aggregate(2), WeatherType::FewClouds);
+        assert_eq!(aggregate(3), WeatherType::Rain);
+        assert_eq!(aggregate(6), WeatherType::Thunder);
+    }
+
+    #[test]
+    fn test_majority() {
+        let forecast = forecast(&[
+            WeatherType::Rain,
+            WeatherType::Clear,
+            WeatherType::Rain,
+            WeatherType::Rain,
+        ]);
+        let aggregate = |current: WeatherType, horizon| {
+            aggregate(&current, &forecast, NOW, horizon, ForecastPolicy::Majority)
+        };
+        // One each, the current weather is earliest.
+        assert_eq!(aggregate(WeatherType::Clear, 1), WeatherType::Clear);
+        assert_eq!(aggregate(WeatherType::Snow, 6), WeatherType::Rain);
+        assert_eq!(aggregate(WeatherType::Clear, 6), WeatherType::Clear);
+        assert_eq!(aggregate(WeatherType::Clear, 9), WeatherType::Rain);
+    }
+
+    #[test]
+    fn test_next_hour() {
+        let forecast = forecast(&[WeatherType::Rain, WeatherType::Snow]);
+        assert_eq!(
+            aggregate(
+                &WeatherType::Clear,
+                &forecast,
+                NOW,
+                3,
+                ForecastPolicy::NextHour
+            ),
+            WeatherType::Rain
+        );
+        // Two hours later the next hour falls into the second period.
+        assert_eq!(
+            aggregate(
+                &WeatherType::Clear,
+                &forecast,
+                NOW + 7200,
+                3,
+                ForecastPolicy::NextHour
+            ),
+            WeatherType::Snow
+        );
+        assert_eq!(
+            aggregate(&WeatherType::Clear, &[], NOW, 3, ForecastPolicy::NextHour),
+            WeatherType::Clear
+        );
+    }
+}
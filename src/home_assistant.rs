@@ -0,0 +1,344 @@
+use crate::error::FehtherError;
+use crate::fetch_weather::{self, Observation};
+use crate::http::HttpClient;
+use crate::secret::ApiKey;
+use crate::types::weathertype::WeatherType;
+use crate::utils;
+use anyhow::{Context, Result};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+// Home Assistant instance, parsed from the [home-assistant] section. Provides the weather
+// and sun entities for the weather source and optional entities to control fehther.
+#[derive(Debug)]
+pub struct HomeAssistant {
+    pub url: Url,
+    // Long-lived access token.
+    pub token: ApiKey,
+    // Weather entity, e.g. weather.home.
+    pub entity: String,
+    pub sun_entity: String,
+    // input_button pressed to show the next wallpaper.
+    pub next_entity: Option<String>,
+    // input_boolean that pauses wallpaper changes while on.
+    pub pause_entity: Option<String>,
+    // Last seen state of the next entity, the time it was last pressed.
+    last_next: Option<String>,
+    // Last seen state of the pause entity.
+    last_paused: Option<bool>,
+}
+
+// Requests from Home Assistant automations.
+#[derive(Debug, Default, PartialEq)]
+pub struct Control {
+    pub next: bool,
+    pub paused: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+struct State {
+    state: String,
+    #[serde(default)]
+    attributes: Map<String, Value>,
+}
+
+impl State {
+    fn number(&self, name: &str) -> Option<f64> {
+        self.attributes.get(name)?.as_f64()
+    }
+
+    fn text(&self, name: &str) -> &str {
+        self.attributes
+            .get(name)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+    }
+}
+
+// Map a Home Assistant weather condition, None for unknown and exceptional conditions.
+pub fn condition(state: &str) -> Option<WeatherType> {
+    Some(match state {
+        "sunny" | "clear-night" => WeatherType::Clear,
+        "partlycloudy" => WeatherType::ScatteredClouds,
+        "cloudy" => WeatherType::OvercastClouds,
+        "fog" => WeatherType::Fog,
+        "rainy" => WeatherType::Rain,
+        "pouring" => WeatherType::HeavyRain,
+        "snowy" => WeatherType::Snow,
+        "snowy-rainy" | "hail" => WeatherType::Sleet,
+        "lightning" | "lightning-rainy" => WeatherType::Thunder,
+        "windy" | "windy-variant" => WeatherType::Squalls,
+        _ => return None,
+    })
+}
+
+// Convert values in the units of the entity to the units OpenWeatherMap reports in metric.
+fn celsius(value: f64, unit: &str) -> f64 {
+    match unit {
+        "°F" => (value - 32.0) / 1.8,
+        "K" => value - 273.15,
+        _ => value,
+    }
+}
+
+fn metres_per_second(value: f64, unit: &str) -> f64 {
+    match unit {
+        "km/h" => value / 3.6,
+        "mph" => value * 0.44704,
+        "kn" => value * 0.514444,
+        "ft/s" => value * 0.3048,
+        _ => value,
+    }
+}
+
+fn hectopascal(value: f64, unit: &str) -> f64 {
+    match unit {
+        "inHg" => value * 33.8639,
+        "mmHg" => value * 1.33322,
+        "kPa" => value * 10.0,
+        "Pa" => value / 100.0,
+        "psi" => value * 68.9476,
+        _ => value,
+    }
+}
+
+fn metres(value: f64, unit: &str) -> f64 {
+    match unit {
+        "km" => value * 1000.0,
+        "mi" => value * 1609.34,
+        _ => value,
+    }
+}
+
+impl HomeAssistant {
+    pub fn new(url: Url, token: ApiKey, entity: String) -> HomeAssistant {
+        HomeAssistant {
+            url,
+            token,
+            entity,
+            sun_entity: "sun.sun".to_string(),
+            next_entity: None,
+            pause_entity: None,
+            last_next: None,
+            last_paused: None,
+        }
+    }
+
+    // Current state of an entity from the REST API.
+    async fn state(&self, http: &HttpClient, entity: &str) -> Result<State> {
+        let url = self.url.join(&format!("api/states/{}", entity))?;
+        let host = url.host_str().unwrap_or_default().to_string();
+        let response = http
+            .client
+            .get(url)
+            .bearer_auth(self.token.expose())
+            .send()
+            .await
+            .with_context(|| FehtherError::Network(host.clone()))?;
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .with_context(|| FehtherError::Network(host.clone()))?;
+        fetch_weather::check_status(status, &body, &host)
+            .with_context(|| format!("Home Assistant entity {}", entity))?;
+        serde_json::from_str(&body).with_context(|| FehtherError::Parse(entity.to_string()))
+    }
+
+    // Current weather from the weather entity, with sunrise and sunset from the sun entity.
+    pub async fn observation(&self, http: &HttpClient) -> Result<Observation> {
+        let weather = self.state(http, &self.entity).await?;
+        let temperature_unit = weather.text("temperature_unit");
+        let wind_unit = weather.text("wind_speed_unit");
+        let mut observation = Observation {
+            weather: condition(&weather.state),
+            temperature: weather
+                .number("temperature")
+                .map(|value| celsius(value, temperature_unit)),
+            feels_like: weather
+                .number("apparent_temperature")
+                .map(|value| celsius(value, temperature_unit)),
+            humidity: weather.number("humidity"),
+            pressure: weather
+                .number("pressure")
+                .map(|value| hectopascal(value, weather.text("pressure_unit"))),
+            wind_speed: weather
+                .number("wind_speed")
+                .map(|value| metres_per_second(value, wind_unit)),
+            wind_gust: weather
+                .number("wind_gust_speed")
+                .map(|value| metres_per_second(value, wind_unit)),
+            clouds: weather.number("cloud_coverage"),
+            visibility: weather
+                .number("visibility")
+                .map(|value| metres(value, weather.text("visibility_unit"))),
+            location: weather.text("friendly_name").to_string(),
+            ..Default::default()
+        };
+        let sun = self.state(http, &self.sun_entity).await?;
+        let rising = utils::parse_timestamp(sun.text("next_rising"));
+        let setting = utils::parse_timestamp(sun.text("next_setting"));
+        // Only the next rising and setting are known. While the sun is up it rose about a
+        // day before the next rising.
+        if let (Some(rising), Some(setting)) = (rising, setting) {
+            observation.sunrise = Some(match sun.state.as_str() {
+                "above_horizon" => rising - 86400,
+                _ => rising,
+            });
+            observation.sunset = Some(setting);
+        }
+        Ok(observation)
+    }
+
+    // Read the control entities. Pressing the next button shows up as a new state, the
+    // state seen first only sets the starting point. The pause entity is only reported
+    // when it changes, so pausing over D-Bus is not undone on the next poll.
+    pub async fn control(&mut self, http: &HttpClient) -> Result<Control> {
+        let mut control = Control::default();
+        if let Some(entity) = &self.next_entity {
+            let pressed = self.state(http, entity).await?.state;
+            control.next = self.last_next.as_ref().is_some_and(|last| *last != pressed);
+            self.last_next = Some(pressed);
+        }
+        if let Some(entity) = &self.pause_entity {
+            let paused = Some(self.state(http, entity).await?.state == "on");
+            if paused != self.last_paused {
+                control.paused = paused;
+                self.last_paused = paused;
+            }
+        }
+        Ok(control)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpSettings;
+    use crate::test_server::serve_paths;
+
+    fn client() -> HttpClient {
+        HttpClient::new(&HttpSettings::default()).unwrap()
+    }
+
+    fn home_assistant(url: &str) -> HomeAssistant {
+        HomeAssistant::new(
+            Url::parse(url).unwrap(),
+            ApiKey::new("token"),
+            "weather.forecast_home".to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_observation() {
+        let (url, requests) = serve_paths(vec![
+            (
+                "/api/states/weather.forecast_home",
+                "200 OK",
+                include_str!("../test_fixtures/ha_weather.json"),
+            ),
+            (
+                "/api/states/sun.sun",
+                "200 OK",
+                include_str!("../test_fixtures/ha_sun.json"),
+            ),
+        ]);
+        let observation = home_assistant(&url).observation(&client()).await.unwrap();
+        assert!(
+            requests
+                .recv()
+                .unwrap()
+                .to_lowercase()
+                .contains("authorization: bearer token")
+        );
+        assert_eq!(observation.weather, Some(WeatherType::Rain));
+        assert_eq!(observation.location, "Forecast Home");
+        assert_eq!(observation.temperature, Some(11.3));
+        assert_eq!(observation.humidity, Some(87.0));
+        // 24.1 km/h
+        assert!((observation.wind_speed.unwrap() - 6.69).abs() < 0.01);
+        assert_eq!(observation.clouds, Some(75.0));
+        // The sun is up, it rose about a day before the next rising.
+        assert_eq!(observation.sunrise, Some(1747108314 - 86400));
+        assert_eq!(observation.sunset, Some(1747077771));
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let (url, _) = serve_paths(vec![(
+            "/api/states/weather.forecast_home",
+            "404 Not Found",
+            r#"{"message": "Entity not found."}"#,
+        )]);
+        let error = home_assistant(&url)
+            .observation(&client())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            crate::error::classify(&error),
+            Some(FehtherError::ProviderRequest(_))
+        ));
+        assert!(format!("{:#}", error).contains("Entity not found."));
+
+        let (url, _) = serve_paths(vec![(
+            "/api/states/",
+            "401 Unauthorized",
+            "401: Unauthorized",
+        )]);
+        let error = home_assistant(&url)
+            .observation(&client())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            crate::error::classify(&error),
+            Some(FehtherError::ProviderAuth(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_control() {
+        let button =
+            |pressed: &'static str| ("/api/states/input_button.fehther_next", "200 OK", pressed);
+        let (url, _) = serve_paths(vec![
+            button(r#"{"state": "2025-05-12T08:00:00+00:00"}"#),
+            button(r#"{"state": "2025-05-12T08:00:00+00:00"}"#),
+            button(r#"{"state": "2025-05-12T09:30:00+00:00"}"#),
+        ]);
+        let mut home_assistant = home_assistant(&url);
+        home_assistant.next_entity = Some("input_button.fehther_next".to_string());
+        let mut next = Vec::new();
+        for _ in 0..3 {
+            next.push(home_assistant.control(&client()).await.unwrap().next);
+        }
+        assert_eq!(next, [false, false, true]);
+
+        let (url, _) = serve_paths(vec![(
+            "/api/states/input_boolean.fehther_pause",
+            "200 OK",
+            r#"{"state": "on"}"#,
+        )]);
+        let mut home_assistant = super::tests::home_assistant(&url);
+        home_assistant.pause_entity = Some("input_boolean.fehther_pause".to_string());
+        assert_eq!(
+            home_assistant.control(&client()).await.unwrap(),
+            Control {
+                next: false,
+                paused: Some(true)
+            }
+        );
+    }
+
+    #[test]
+    fn test_units() {
+        assert_eq!(
+            condition("partlycloudy"),
+            Some(WeatherType::ScatteredClouds)
+        );
+        assert_eq!(condition("exceptional"), None);
+        assert!((celsius(50.0, "°F") - 10.0).abs() < 1e-9);
+        assert!((metres_per_second(36.0, "km/h") - 10.0).abs() < 1e-9);
+        assert!((hectopascal(29.92, "inHg") - 1013.2).abs() < 0.1);
+        assert_eq!(metres(10.0, "km"), 10000.0);
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::{Context, Result};
+use std::fmt;
+use std::process::{Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Commands run on changes, parsed from the [hooks] section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HookSettings {
+    pub on_wallpaper_change: Option<String>,
+    pub on_daytime_change: Option<String>,
+    pub on_weather_change: Option<String>,
+    // Seconds a hook may run before it is killed.
+    pub timeout: u64,
+}
+
+impl Default for HookSettings {
+    fn default() -> Self {
+        HookSettings {
+            on_wallpaper_change: None,
+            on_daytime_change: None,
+            on_weather_change: None,
+            timeout: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Wallpaper,
+    Daytime,
+    Weather,
+}
+
+// Named like the config keys, without the on- prefix.
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Event::Wallpaper => write!(f, "wallpaper-change"),
+            Event::Daytime => write!(f, "daytime-change"),
+            Event::Weather => write!(f, "weather-change"),
+        }
+    }
+}
+
+impl HookSettings {
+    pub fn command(&self, event: Event) -> Option<&str> {
+        match event {
+            Event::Wallpaper => self.on_wallpaper_change.as_deref(),
+            Event::Daytime => self.on_daytime_change.as_deref(),
+            Event::Weather => self.on_weather_change.as_deref(),
+        }
+    }
+}
+
+// Start a hook with extra environment variables and return right away. A thread waits
+// for it, kills it after the timeout and logs failures.
+pub fn run(
+    command: &str,
+    event: Event,
+    env: Vec<(&'static str, String)>,
+    timeout: Duration,
+) -> Result<JoinHandle<()>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env)
+        .env("FEHTHER_EVENT", event.to_string())
+        .stdin(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Could not run on-{} hook: {}", event, command))?;
+    let started = Instant::now();
+    Ok(thread::spawn(move || {
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) if status.success() => return,
+                Ok(Some(status)) => {
+                    eprintln!("fehther: on-{} hook exited with {}", event, status);
+                    return;
+                }
+                Ok(None) if started.elapsed() >= timeout => {
+                    child.kill().ok();
+                    child.wait().ok();
+                    eprintln!(
+                        "fehther: on-{} hook killed after {} seconds",
+                        event,
+                        timeout.as_secs_f64()
+                    );
+                    return;
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(100)),
+                Err(error) => {
+                    eprintln!("fehther: on-{} hook failed: {}", event, error);
+                    return;
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("env");
+        let command = format!(
+            "sleep 1; echo \"$FEHTHER_EVENT $FEHTHER_DAYTIME $FEHTHER_IMAGE\" > {}",
+            output.display()
+        );
+        let started = Instant::now();
+        let hook = run(
+            &command,
+            Event::Daytime,
+            vec![
+                ("FEHTHER_DAYTIME", "night".to_string()),
+                ("FEHTHER_IMAGE", "/wallpapers/night/lake.jpg".to_string()),
+            ],
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        // fehther carries on while the hook runs.
+        assert!(started.elapsed() < Duration::from_secs(1));
+        hook.join().unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "daytime-change night /wallpapers/night/lake.jpg\n"
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let started = Instant::now();
+        run(
+            "sleep 10",
+            Event::Weather,
+            Vec::new(),
+            Duration::from_millis(200),
+        )
+        .unwrap()
+        .join()
+        .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::{Context, Result};
+use reqwest::{Proxy, Url};
+use std::time::Duration;
+
+// HTTP client settings, parsed from the [http] section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpSettings {
+    // Seconds to wait for a connection.
+    pub connect_timeout: u64,
+    // Seconds for a whole request, including reading the response.
+    pub timeout: u64,
+    // Proxy for all requests, e.g. http://proxy:3128. Without it the HTTP_PROXY and
+    // HTTPS_PROXY environment variables are used.
+    pub proxy: Option<String>,
+    // Base url of the OpenWeatherMap API, for mirrors or a local stub.
+    pub base_url: String,
+    pub user_agent: String,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        HttpSettings {
+            connect_timeout: 10,
+            timeout: 30,
+            proxy: None,
+            base_url: "https://api.openweathermap.org/".to_string(),
+            user_agent: format!("fehther/{}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+}
+
+// Client shared by all requests, so connections are reused between polls.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    pub client: reqwest::Client,
+    pub base_url: Url,
+}
+
+impl HttpClient {
+    pub fn new(settings: &HttpSettings) -> Result<HttpClient> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(settings.connect_timeout))
+            .timeout(Duration::from_secs(settings.timeout))
+            .user_agent(&settings.user_agent);
+        if let Some(proxy) = &settings.proxy {
+            builder = builder
+                .proxy(Proxy::all(proxy).with_context(|| format!("Invalid proxy: {}", proxy))?);
+        }
+        // Endpoints are joined to the base url, which only works with a trailing slash.
+        let base_url = match settings.base_url.ends_with('/') {
+            true => settings.base_url.clone(),
+            false => format!("{}/", settings.base_url),
+        };
+        Ok(HttpClient {
+            client: builder.build()?,
+            base_url: Url::parse(&base_url)
+                .with_context(|| format!("Invalid base-url: {}", settings.base_url))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client() {
+        let client = HttpClient::new(&HttpSettings {
+            base_url: "http://127.0.0.1:8080/owm".to_string(),
+            proxy: Some("http://proxy.local:3128".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            client.base_url.join("data/2.5/weather").unwrap().as_str(),
+            "http://127.0.0.1:8080/owm/data/2.5/weather"
+        );
+        assert!(
+            HttpClient::new(&HttpSettings {
+                base_url: "not a url".to_string(),
+                ..Default::default()
+            })
+            .is_err()
+        );
+        assert!(
+            HttpClient::new(&HttpSettings {
+                proxy: Some("::".to_string()),
+                ..Default::default()
+            })
+            .is_err()
+        );
+    }
+}
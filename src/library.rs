@@ -0,0 +1,553 @@
+use crate::error::FehtherError;
+use crate::filter::ImageInfo;
+use crate::scan::{self, ScanOptions};
+use crate::tags;
+use crate::utils;
+use anyhow::{Context, Result};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use rusqlite::{Connection, OptionalExtension, named_params, params};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS images (
+        path TEXT PRIMARY KEY,
+        size INTEGER NOT NULL,
+        modified INTEGER NOT NULL,
+        width INTEGER,
+        height INTEGER,
+        inspected INTEGER NOT NULL DEFAULT 0,
+        tags TEXT NOT NULL DEFAULT '',
+        custom_tags TEXT NOT NULL DEFAULT '',
+        show_count INTEGER NOT NULL DEFAULT 0,
+        last_shown INTEGER
+    );";
+
+const COLUMNS: &str =
+    "path, size, modified, width, height, inspected, tags, custom_tags, show_count, last_shown";
+
+// Paths starting with :prefix. A range keeps this byte-wise for non-ASCII paths, no valid
+// UTF-8 text sorts after the 0xff byte.
+const UNDER: &str = "(path >= :prefix AND path < :prefix || x'ff')";
+
+// A single image in the library.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub path: String,
+    pub size: u64,
+    pub modified: u64,
+    pub info: Option<ImageInfo>,
+    // True once the header was read, info stays None for images that could not be decoded.
+    pub inspected: bool,
+    pub tags: Vec<String>,
+    pub custom_tags: Vec<String>,
+    pub show_count: u32,
+    pub last_shown: Option<u64>,
+}
+
+impl Record {
+    // Image found by a plain folder scan, used while the library cannot be opened.
+    pub fn new(path: String) -> Record {
+        Record {
+            path,
+            size: 0,
+            modified: 0,
+            info: None,
+            inspected: false,
+            tags: Vec::new(),
+            custom_tags: Vec::new(),
+            show_count: 0,
+            last_shown: None,
+        }
+    }
+
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter()
+            .all(|tag| self.tags.contains(tag) || self.custom_tags.contains(tag))
+    }
+
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Record> {
+        let width: Option<u32> = row.get(3)?;
+        let height: Option<u32> = row.get(4)?;
+        let split = |tags: String| tags.split_whitespace().map(String::from).collect();
+        Ok(Record {
+            path: row.get(0)?,
+            size: row.get(1)?,
+            modified: row.get(2)?,
+            info: width
+                .zip(height)
+                .map(|(width, height)| ImageInfo { width, height }),
+            inspected: row.get(5)?,
+            tags: split(row.get(6)?),
+            custom_tags: split(row.get(7)?),
+            show_count: row.get(8)?,
+            last_shown: row.get(9)?,
+        })
+    }
+}
+
+// Summary printed by `fehther library stats`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Stats {
+    pub images: u64,
+    pub total_size: u64,
+    pub inspected: u64,
+    pub corrupt: u64,
+    pub never_shown: u64,
+    pub most_shown: Vec<(String, u32)>,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "images: {}", self.images)?;
+        writeln!(
+            f,
+            "total size: {:.1} MiB",
+            self.total_size as f64 / 1048576.0
+        )?;
+        writeln!(f, "inspected: {}", self.inspected)?;
+        writeln!(f, "corrupt: {}", self.corrupt)?;
+        writeln!(f, "never shown: {}", self.never_shown)?;
+        if !self.most_shown.is_empty() {
+            writeln!(f, "most shown:")?;
+            for (path, count) in &self.most_shown {
+                writeln!(f, "  {:>5}  {}", count, path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Persistent index of all images below the wallpaper root folder.
+pub struct Library {
+    conn: Connection,
+}
+
+impl Library {
+    // Default location, $XDG_DATA_HOME/fehther/library.db or ~/.local/share/fehther/library.db.
+    pub fn database_file() -> PathBuf {
+        utils::fetch_data_dir().join("library.db")
+    }
+
+    pub fn open(database_file: &Path) -> Result<Library> {
+        if let Some(parent) = database_file.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| FehtherError::Filesystem(parent.to_path_buf()))?;
+        }
+        let conn = Connection::open(database_file)
+            .with_context(|| FehtherError::Filesystem(database_file.to_path_buf()))?;
+        // The file watcher writes from its own connection.
+        conn.busy_timeout(Duration::from_secs(5))?;
+        Library::init(conn)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Library> {
+        Library::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Library> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Library { conn })
+    }
+
+    // Bring the library up to date with the wallpaper folder. Only new and modified images are
+    // read, removed images are dropped. Returns the number of updated and removed images.
+    pub fn rescan(&self, root: &Path, options: &ScanOptions) -> Result<(usize, usize)> {
+        let images = scan::find_images(root, &full_scan(options));
+        let transaction = self.conn.unchecked_transaction()?;
+        let known: HashMap<String, (u64, u64)> = {
+            let mut statement = transaction.prepare("SELECT path, size, modified FROM images")?;
+            statement
+                .query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?
+                .collect::<rusqlite::Result<_>>()?
+        };
+
+        let mut updated = 0;
+        for image in &images {
+            let (size, modified) = file_stamp(Path::new(image));
+            if known.get(image) != Some(&(size, modified)) {
+                upsert(&transaction, root, image, size, modified)?;
+                updated += 1;
+            }
+        }
+
+        let mut removed = 0;
+        for path in known
+            .keys()
+            .filter(|path| images.binary_search(path).is_err())
+        {
+            removed += transaction.execute("DELETE FROM images WHERE path = ?1", [path])?;
+        }
+        transaction.commit()?;
+        Ok((updated, removed))
+    }
+
+    // Add or refresh a single file, used by the file watcher.
+    pub fn update_image(&self, root: &Path, image: &Path, options: &ScanOptions) -> Result<()> {
+        let relative = image.strip_prefix(root).unwrap_or(image);
+        if !image.is_file() || !full_scan(options).accepts(relative) {
+            return Ok(());
+        }
+        let (size, modified) = file_stamp(image);
+        let path = image.display().to_string();
+        let known: Option<(u64, u64)> = self
+            .conn
+            .query_row(
+                "SELECT size, modified FROM images WHERE path = ?1",
+                [&path],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        if known != Some((size, modified)) {
+            upsert(&self.conn, root, &path, size, modified)?;
+        }
+        Ok(())
+    }
+
+    // Remove an image, or every image below a removed folder.
+    pub fn remove_image(&self, image: &Path) -> Result<usize> {
+        let path = image.display().to_string();
+        Ok(self.conn.execute(
+            &format!("DELETE FROM images WHERE path = :path OR {}", UNDER),
+            named_params! {":path": path, ":prefix": format!("{}/", path)},
+        )?)
+    }
+
+    pub fn images(&self) -> Result<Vec<Record>> {
+        let mut statement = self
+            .conn
+            .prepare(&format!("SELECT {} FROM images ORDER BY path", COLUMNS))?;
+        Ok(statement
+            .query_map([], Record::from_row)?
+            .collect::<rusqlite::Result<_>>()?)
+    }
+
+    // All images anywhere below a folder.
+    pub fn images_under(&self, folder: &Path) -> Result<Vec<Record>> {
+        let prefix = format!("{}/", folder.display().to_string().trim_end_matches('/'));
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {} FROM images WHERE {} ORDER BY path",
+            COLUMNS, UNDER
+        ))?;
+        Ok(statement
+            .query_map(named_params! {":prefix": prefix}, Record::from_row)?
+            .collect::<rusqlite::Result<_>>()?)
+    }
+
+    // Image dimensions, read from the header on first use and stored until the file changes.
+    pub fn inspect(&self, record: &Record) -> Option<ImageInfo> {
+        if record.inspected {
+            return record.info;
+        }
+        let info = crate::filter::read_header(Path::new(&record.path));
+        self.conn
+            .execute(
+                "UPDATE images SET width = ?1, height = ?2, inspected = 1 WHERE path = ?3",
+                params![
+                    info.map(|info| info.width),
+                    info.map(|info| info.height),
+                    record.path
+                ],
+            )
+            .ok();
+        info
+    }
+
+    pub fn mark_shown(&self, image: &str, now: u64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE images SET show_count = show_count + 1, last_shown = ?1 WHERE path = ?2",
+            params![now, image],
+        )?;
+        Ok(())
+    }
+
+    // Replace the hand-picked tags of an image, these are kept when the image is rescanned.
+    pub fn set_custom_tags(&self, image: &str, custom_tags: &[String]) -> Result<bool> {
+        Ok(self.conn.execute(
+            "UPDATE images SET custom_tags = ?1 WHERE path = ?2",
+            params![custom_tags.join(" "), image],
+        )? > 0)
+    }
+
+    pub fn stats(&self) -> Result<Stats> {
+        let mut stats = self.conn.query_row(
+            "SELECT count(*), coalesce(sum(size), 0), coalesce(sum(inspected), 0),
+                    coalesce(sum(inspected AND width IS NULL), 0),
+                    coalesce(sum(show_count = 0), 0)
+             FROM images",
+            [],
+            |row| {
+                Ok(Stats {
+                    images: row.get(0)?,
+                    total_size: row.get(1)?,
+                    inspected: row.get(2)?,
+                    corrupt: row.get(3)?,
+                    never_shown: row.get(4)?,
+                    most_shown: Vec::new(),
+                })
+            },
+        )?;
+        let mut statement = self.conn.prepare(
+            "SELECT path, show_count FROM images WHERE show_count > 0
+             ORDER BY show_count DESC, path LIMIT 5",
+        )?;
+        stats.most_shown = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(stats)
+    }
+}
+
+// Pick the least shown images, ties are broken at random so equally shown images rotate.
+pub fn select(mut records: Vec<Record>, count: usize) -> Vec<String> {
+    let mut selected = Vec::new();
+    while selected.len() < count {
+        let Some(least) = records.iter().map(|record| record.show_count).min() else {
+            break;
+        };
+        let candidates: Vec<usize> = (0..records.len())
+            .filter(|&i| records[i].show_count == least)
+            .collect();
+        let pick = candidates[utils::random_index(candidates.len())];
+        selected.push(records.swap_remove(pick).path);
+    }
+    selected
+}
+
+// The library always indexes the whole wallpaper folder, depth and glob rules are applied
+// per folder when images are selected.
+fn full_scan(options: &ScanOptions) -> ScanOptions {
+    ScanOptions {
+        recursive: true,
+        max_depth: None,
+        include: Vec::new(),
+        exclude: Vec::new(),
+        ..options.clone()
+    }
+}
+
+fn file_stamp(image: &Path) -> (u64, u64) {
+    let size = fs::metadata(image).map_or(0, |metadata| metadata.len());
+    (size, utils::modified_secs(image))
+}
+
+// Insert or refresh an image. Tags are re-read and dimensions are inspected again on next use,
+// show count and custom tags are kept.
+fn upsert(conn: &Connection, root: &Path, image: &str, size: u64, modified: u64) -> Result<()> {
+    let path = Path::new(image);
+    let mut image_tags = tags::path_tags(root, path);
+    image_tags.extend(tags::xmp_tags(path));
+    image_tags.sort();
+    image_tags.dedup();
+    conn.execute(
+        "INSERT INTO images (path, size, modified, tags) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(path) DO UPDATE SET size = excluded.size, modified = excluded.modified,
+             tags = excluded.tags, width = NULL, height = NULL, inspected = 0",
+        params![image, size, modified, image_tags.join(" ")],
+    )?;
+    Ok(())
+}
+
+// Keep the library up to date while fehther runs. The returned watcher must be kept alive.
+pub fn watch(
+    database_file: &Path,
+    root: &Path,
+    options: &ScanOptions,
+) -> Result<RecommendedWatcher> {
+    let library = Library::open(database_file)?;
+    let (root_path, options) = (root.to_path_buf(), options.clone());
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        for path in event.paths {
+            let result = if path.is_dir() {
+                scan::find_images(&path, &full_scan(&options))
+                    .iter()
+                    .try_for_each(|image| {
+                        library.update_image(&root_path, Path::new(image), &options)
+                    })
+            } else if path.exists() {
+                library.update_image(&root_path, &path, &options)
+            } else {
+                library.remove_image(&path).map(|_| ())
+            };
+            if let Err(error) = result {
+                eprintln!("fehther: failed to update library: {:#}", error);
+            }
+        }
+    })?;
+    watcher.watch(root, RecursiveMode::Recursive)?;
+    Ok(watcher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> (tempfile::TempDir, Library) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("day/rain")).unwrap();
+        for file in ["day/a.jpg", "day/rain/b.png", "night_rain.jpg", "notes.txt"] {
+            fs::write(dir.path().join(file), b"").unwrap();
+        }
+        (dir, Library::open_in_memory().unwrap())
+    }
+
+    fn names(root: &Path, records: &[Record]) -> Vec<String> {
+        records
+            .iter()
+            .map(|record| {
+                Path::new(&record.path)
+                    .strip_prefix(root)
+                    .unwrap()
+                    .display()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rescan_is_incremental() {
+        let (dir, library) = fixture();
+        let root = dir.path();
+        let options = ScanOptions::default();
+        assert_eq!(library.rescan(root, &options).unwrap(), (3, 0));
+        assert_eq!(library.rescan(root, &options).unwrap(), (0, 0));
+
+        fs::remove_file(root.join("day/a.jpg")).unwrap();
+        fs::write(root.join("day/rain/b.png"), b"changed").unwrap();
+        assert_eq!(library.rescan(root, &options).unwrap(), (1, 1));
+        assert_eq!(
+            names(root, &library.images().unwrap()),
+            vec!["day/rain/b.png", "night_rain.jpg"]
+        );
+    }
+
+    #[test]
+    fn test_images_under_and_tags() {
+        let (dir, library) = fixture();
+        let root = dir.path();
+        library.rescan(root, &ScanOptions::default()).unwrap();
+
+        let day = library.images_under(&root.join("day")).unwrap();
+        assert_eq!(names(root, &day), vec!["day/a.jpg", "day/rain/b.png"]);
+
+        let rain = vec!["rain".to_string()];
+        let tagged: Vec<Record> = library
+            .images()
+            .unwrap()
+            .into_iter()
+            .filter(|record| record.has_tags(&rain))
+            .collect();
+        assert_eq!(
+            names(root, &tagged),
+            vec!["day/rain/b.png", "night_rain.jpg"]
+        );
+
+        let image = root.join("day/a.jpg").display().to_string();
+        assert!(
+            library
+                .set_custom_tags(&image, &["rain".to_string()])
+                .unwrap()
+        );
+        library.rescan(root, &ScanOptions::default()).unwrap();
+        let record = &library.images_under(&root.join("day")).unwrap()[0];
+        assert!(record.has_tags(&rain));
+    }
+
+    #[test]
+    fn test_show_count_and_stats() {
+        let (dir, library) = fixture();
+        let root = dir.path();
+        library.rescan(root, &ScanOptions::default()).unwrap();
+        let image = root.join("night_rain.jpg").display().to_string();
+        library.mark_shown(&image, 100).unwrap();
+        library.mark_shown(&image, 200).unwrap();
+
+        let records = library.images().unwrap();
+        let record = records.iter().find(|record| record.path == image).unwrap();
+        assert_eq!((record.show_count, record.last_shown), (2, Some(200)));
+
+        // Empty fixture files cannot be decoded.
+        assert_eq!(library.inspect(record), None);
+        let stats = library.stats().unwrap();
+        assert_eq!(stats.images, 3);
+        assert_eq!(stats.inspected, 1);
+        assert_eq!(stats.corrupt, 1);
+        assert_eq!(stats.never_shown, 2);
+        assert_eq!(stats.most_shown, vec![(image, 2)]);
+    }
+
+    #[test]
+    fn test_select_least_shown() {
+        let (dir, library) = fixture();
+        let root = dir.path();
+        library.rescan(root, &ScanOptions::default()).unwrap();
+        let images: Vec<String> = library
+            .images()
+            .unwrap()
+            .into_iter()
+            .map(|record| record.path)
+            .collect();
+        library.mark_shown(&images[0], 1).unwrap();
+        library.mark_shown(&images[0], 2).unwrap();
+        library.mark_shown(&images[1], 3).unwrap();
+
+        let records = library.images().unwrap();
+        assert_eq!(select(records.clone(), 1), vec![images[2].clone()]);
+        assert_eq!(
+            select(records.clone(), 2),
+            vec![images[2].clone(), images[1].clone()]
+        );
+        assert_eq!(select(records, 5).len(), 3);
+    }
+
+    #[test]
+    fn test_update_and_remove_image() {
+        let (dir, library) = fixture();
+        let root = dir.path();
+        let options = ScanOptions::default();
+        library
+            .update_image(root, &root.join("day/a.jpg"), &options)
+            .unwrap();
+        library
+            .update_image(root, &root.join("notes.txt"), &options)
+            .unwrap();
+        library
+            .update_image(root, &root.join("day/rain/b.png"), &options)
+            .unwrap();
+        assert_eq!(library.images().unwrap().len(), 2);
+        assert_eq!(library.remove_image(&root.join("day")).unwrap(), 2);
+        assert!(library.images().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_non_ascii_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for file in ["été/é.jpg", "été/pluie/b.png", "étéx/c.jpg", "zon.jpg"] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        let library = Library::open_in_memory().unwrap();
+        library.rescan(root, &ScanOptions::default()).unwrap();
+        assert_eq!(
+            names(root, &library.images_under(&root.join("été")).unwrap()),
+            vec!["été/pluie/b.png", "été/é.jpg"]
+        );
+        assert_eq!(library.remove_image(&root.join("été")).unwrap(), 2);
+        assert_eq!(
+            names(root, &library.images().unwrap()),
+            vec!["zon.jpg", "étéx/c.jpg"]
+        );
+    }
+}
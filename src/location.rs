@@ -0,0 +1,143 @@
+use crate::fmt;
+use crate::http::HttpClient;
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+
+// Ways to tell the weather provider where to look, from most to least precise.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Location {
+    Coordinates { lat: f64, lon: f64 },
+    // OpenWeatherMap city ID, unambiguous unlike city names.
+    CityId(u64),
+    Zip { zip: String, country: String },
+    City { city: String, country: String },
+    // Resolved to coordinates from the public IP address before the first request.
+    Ip,
+}
+
+// fmt trait for logging
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::Coordinates { lat, lon } => write!(f, "{:.4}, {:.4}", lat, lon),
+            Location::CityId(id) => write!(f, "city id {}", id),
+            Location::Zip { zip, country } => write!(f, "zip {}, {}", zip, country),
+            Location::City { city, country } => write!(f, "{}, {}", city, country),
+            Location::Ip => write!(f, "ip geolocation"),
+        }
+    }
+}
+
+impl Location {
+    // Query parameters selecting this location in the OpenWeatherMap API.
+    pub fn query(&self) -> Result<Vec<(&'static str, String)>> {
+        Ok(match self {
+            Location::Coordinates { lat, lon } => {
+                vec![("lat", lat.to_string()), ("lon", lon.to_string())]
+            }
+            Location::CityId(id) => vec![("id", id.to_string())],
+            Location::Zip { zip, country } => vec![("zip", join_country(zip, country))],
+            Location::City { city, country } => vec![("q", join_country(city, country))],
+            Location::Ip => return Err(anyhow!("location has not been resolved yet")),
+        })
+    }
+}
+
+fn join_country(name: &str, country: &str) -> String {
+    match country.is_empty() {
+        true => name.to_string(),
+        false => format!("{},{}", name, country),
+    }
+}
+
+// Check coordinates from the config or a location source.
+pub fn coordinates(lat: f64, lon: f64) -> Result<Location> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(anyhow!("coordinates out of range: {}, {}", lat, lon));
+    }
+    Ok(Location::Coordinates { lat, lon })
+}
+
+// Response of an IP geolocation service. Field names differ between services.
+#[derive(Deserialize, Debug)]
+struct IpLocation {
+    #[serde(alias = "latitude")]
+    lat: f64,
+    #[serde(alias = "longitude")]
+    lon: f64,
+}
+
+// Find the current position from the public IP address, using a service that answers
+// with JSON containing lat and lon, or latitude and longitude.
+pub async fn geolocate(http: &HttpClient, url: &str) -> Result<Location> {
+    let body = http
+        .client
+        .get(url)
+        .send()
+        .await
+        .map_err(|error| error.without_url())?
+        .error_for_status()
+        .map_err(|error| error.without_url())?
+        .text()
+        .await?;
+    let position: IpLocation = serde_json::from_str(&body)
+        .with_context(|| format!("Invalid geolocation response from {}", url))?;
+    coordinates(position.lat, position.lon)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpSettings;
+    use crate::test_server::serve;
+
+    #[test]
+    fn test_query() {
+        let city = Location::City {
+            city: "São Paulo".to_string(),
+            country: "BR".to_string(),
+        };
+        assert_eq!(
+            city.query().unwrap(),
+            vec![("q", "São Paulo,BR".to_string())]
+        );
+        let coordinates = coordinates(52.37, -4.5).unwrap();
+        assert_eq!(
+            coordinates.query().unwrap(),
+            vec![("lat", "52.37".to_string()), ("lon", "-4.5".to_string())]
+        );
+        assert!(Location::Ip.query().is_err());
+        assert!(super::coordinates(91.0, 0.0).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_geolocate() {
+        let http = HttpClient::new(&HttpSettings::default()).unwrap();
+        let url = serve(
+            "200 OK",
+            r#"{"status": "success", "city": "Utrecht", "lat": 52.09, "lon": 5.12}"#,
+        );
+        assert_eq!(
+            geolocate(&http, &format!("{}/json", url)).await.unwrap(),
+            Location::Coordinates {
+                lat: 52.09,
+                lon: 5.12
+            }
+        );
+        let url = serve("200 OK", r#"{"latitude": -33.9, "longitude": 18.4}"#);
+        assert_eq!(
+            geolocate(&http, &format!("{}/json", url)).await.unwrap(),
+            Location::Coordinates {
+                lat: -33.9,
+                lon: 18.4
+            }
+        );
+        let url = serve(
+            "200 OK",
+            r#"{"status": "fail", "message": "reserved range"}"#,
+        );
+        assert!(geolocate(&http, &format!("{}/json", url)).await.is_err());
+        let url = serve("503 Service Unavailable", "");
+        assert!(geolocate(&http, &format!("{}/json", url)).await.is_err());
+    }
+}
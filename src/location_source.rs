@@ -0,0 +1,210 @@
+use crate::types::default_types::COMMAND_TIMEOUT;
+use crate::utils;
+use anyhow::{Context, Result, anyhow};
+use std::fmt;
+use std::time::Duration;
+use zbus::blocking::Connection;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Value};
+
+// Mean earth radius in kilometres.
+const EARTH_RADIUS: f64 = 6371.0;
+
+// Great circle distance in kilometres between two (lat, lon) positions.
+pub fn haversine((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = (lat2 - lat1).to_radians();
+    let d_lambda = (lon2 - lon1).to_radians();
+    let a = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+// Something that knows where this machine currently is.
+pub trait LocationSource: fmt::Debug {
+    // Current (lat, lon), None while the position is not known yet.
+    fn position(&mut self) -> Result<Option<(f64, f64)>>;
+}
+
+// Runs a user command that prints "lat lon" or "lat,lon".
+#[derive(Debug)]
+pub struct CommandSource {
+    pub command: String,
+}
+
+impl LocationSource for CommandSource {
+    fn position(&mut self) -> Result<Option<(f64, f64)>> {
+        let output = utils::run_command(&self.command, Duration::from_secs(COMMAND_TIMEOUT))?;
+        parse_position(&output).map(Some)
+    }
+}
+
+fn parse_position(text: &str) -> Result<(f64, f64)> {
+    let values: Vec<f64> = text
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse())
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("Invalid position: {}", text.trim()))?;
+    match values[..] {
+        [lat, lon] => Ok((lat, lon)),
+        _ => Err(anyhow!("Invalid position: {}", text.trim())),
+    }
+}
+
+const GEOCLUE: &str = "org.freedesktop.GeoClue2";
+// City level accuracy is enough for weather and sunrise times.
+const GEOCLUE_ACCURACY_CITY: u32 = 4;
+
+// Asks GeoClue over the system bus. The client is created on first use.
+#[derive(Debug, Default)]
+pub struct GeoClueSource {
+    client: Option<(Connection, OwnedObjectPath)>,
+}
+
+impl GeoClueSource {
+    fn start(&self) -> Result<(Connection, OwnedObjectPath)> {
+        let connection = Connection::system()?;
+        let client: OwnedObjectPath = connection
+            .call_method(
+                Some(GEOCLUE),
+                "/org/freedesktop/GeoClue2/Manager",
+                Some("org.freedesktop.GeoClue2.Manager"),
+                "GetClient",
+                &(),
+            )?
+            .body()
+            .deserialize()?;
+        let set = |name: &str, value: Value| {
+            connection.call_method(
+                Some(GEOCLUE),
+                &client,
+                Some("org.freedesktop.DBus.Properties"),
+                "Set",
+                &("org.freedesktop.GeoClue2.Client", name, value),
+            )
+        };
+        set("DesktopId", Value::from("fehther"))?;
+        set("RequestedAccuracyLevel", Value::from(GEOCLUE_ACCURACY_CITY))?;
+        connection.call_method(
+            Some(GEOCLUE),
+            &client,
+            Some("org.freedesktop.GeoClue2.Client"),
+            "Start",
+            &(),
+        )?;
+        Ok((connection, client))
+    }
+}
+
+fn get_property(
+    connection: &Connection,
+    path: &OwnedObjectPath,
+    interface: &str,
+    name: &str,
+) -> Result<OwnedValue> {
+    Ok(connection
+        .call_method(
+            Some(GEOCLUE),
+            path,
+            Some("org.freedesktop.DBus.Properties"),
+            "Get",
+            &(interface, name),
+        )?
+        .body()
+        .deserialize()?)
+}
+
+impl LocationSource for GeoClueSource {
+    fn position(&mut self) -> Result<Option<(f64, f64)>> {
+        if self.client.is_none() {
+            self.client = Some(self.start().context("Could not start GeoClue client")?);
+        }
+        let (connection, client) = self.client.as_ref().unwrap();
+        let location: OwnedObjectPath = get_property(
+            connection,
+            client,
+            "org.freedesktop.GeoClue2.Client",
+            "Location",
+        )?
+        .try_into()?;
+        // GeoClue uses "/" until the first position is known.
+        if location.as_str() == "/" {
+            return Ok(None);
+        }
+        let coordinate = |name: &str| -> Result<f64> {
+            Ok(get_property(
+                connection,
+                &location,
+                "org.freedesktop.GeoClue2.Location",
+                name,
+            )?
+            .try_into()?)
+        };
+        Ok(Some((coordinate("Latitude")?, coordinate("Longitude")?)))
+    }
+}
+
+// Replays a list of positions, for tests.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct MockSource(pub Vec<Option<(f64, f64)>>);
+
+#[cfg(test)]
+impl LocationSource for MockSource {
+    fn position(&mut self) -> Result<Option<(f64, f64)>> {
+        match self.0.is_empty() {
+            true => Err(anyhow!("no more positions")),
+            false => Ok(self.0.remove(0)),
+        }
+    }
+}
+
+// Location following settings, parsed from the [location] section.
+#[derive(Debug)]
+pub struct LocationFollower {
+    pub source: Box<dyn LocationSource>,
+    // Smallest move in kilometres that switches to the new position.
+    pub min_distance: f64,
+    // Minutes between position checks.
+    pub interval: i32,
+    pub timer: i32,
+}
+
+impl LocationFollower {
+    // Check if the timer has reached the interval, the first check is right away.
+    pub fn is_due(&mut self) -> bool {
+        self.timer -= 1;
+        if self.timer > 0 {
+            return false;
+        }
+        self.timer = self.interval;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_haversine() {
+        // Amsterdam to Utrecht is about 35 km.
+        let distance = haversine((52.3676, 4.9041), (52.0907, 5.1214));
+        assert!((distance - 34.5).abs() < 1.0, "{}", distance);
+        assert_eq!(haversine((10.0, 20.0), (10.0, 20.0)), 0.0);
+    }
+
+    #[test]
+    fn test_command_source() {
+        let mut source = CommandSource {
+            command: "echo 52.09,5.12".to_string(),
+        };
+        assert_eq!(source.position().unwrap(), Some((52.09, 5.12)));
+        assert_eq!(parse_position("-33.9 18.4\n").unwrap(), (-33.9, 18.4));
+        assert!(parse_position("52.09").is_err());
+        assert!(parse_position("unknown").is_err());
+        let mut source = CommandSource {
+            command: "exit 1".to_string(),
+        };
+        assert!(source.position().is_err());
+    }
+}
@@ -1,64 +1,442 @@
-use crate::fetch_weather::WeatherResponse;
+use crate::error::FehtherError;
+use crate::fetch_weather::Observation;
+use crate::library::Library;
+use crate::location::Location;
+use crate::secret::ApiKey;
 use crate::settings::Settings;
 use crate::types::modes::Mode;
 use crate::types::weathertype::WeatherType;
+use crate::weather_source::WeatherSource;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::fmt;
-use std::{fs, path::PathBuf, process::Command, thread};
+use std::{fs, path::PathBuf};
 use tokio::time;
 
+mod blend;
 mod config;
+mod dbus;
+mod debounce;
+mod effects;
+mod error;
 mod fetch_weather;
+mod filter;
+mod forecast;
+mod home_assistant;
+mod hooks;
+mod http;
+mod library;
+mod location;
+mod location_source;
+mod overlay;
+mod palette;
+mod rules;
+mod scan;
+mod secret;
+mod setter;
 mod settings;
+mod status;
+mod tags;
+#[cfg(test)]
+mod test_server;
+mod transition;
 mod types;
 mod utils;
+mod weather_source;
 
 // Main loop
 async fn wallpaper_manager_loop(settings: &mut Settings) -> Result<(), anyhow::Error> {
+    // Bring the library up to date, then keep it updated while running. Without a library
+    // wallpapers are picked by scanning folders.
+    let root = std::path::Path::new(&settings.path);
+    if let Err(error) = Library::open(&settings.library_file)
+        .and_then(|library| library.rescan(root, &settings.scan))
+    {
+        eprintln!(
+            "fehther: library unavailable, scanning folders: {:#}",
+            error
+        );
+    }
+    let _watcher = match settings.library_watch {
+        true => library::watch(&settings.library_file, root, &settings.scan)
+            .map_err(|error| eprintln!("fehther: not watching wallpaper folder: {:#}", error))
+            .ok(),
+        false => None,
+    };
+    let mut service = match settings.dbus {
+        true => dbus::Service::start()
+            .await
+            .map_err(|error| eprintln!("fehther: D-Bus service not available: {:#}", error))
+            .ok(),
+        false => None,
+    };
     loop {
+        settings.check_library_rescan();
+        settings.check_location();
+        check_home_assistant(settings).await;
         // If cycle mode is on, change wallpaper if interval is reached.
         if settings.modes.contains(&Mode::Cycle) {
             settings.check_cycle_mode()
         }
-        // Only fetch weather data when weather or day mode is on.
-        if settings.modes.contains(&Mode::Daytime) || settings.modes.contains(&Mode::Weather) {
+        // Only fetch weather data when weather, day or blend mode, weather effects or the
+        // overlay are on.
+        if (settings.modes.contains(&Mode::Daytime)
+            || settings.modes.contains(&Mode::Weather)
+            || settings.modes.contains(&Mode::Blend)
+            || settings.effects.enabled
+            || settings.overlay.enabled)
+            && settings.check_weather_backoff()
+        {
+            resolve_location(settings).await;
+            // If forecast mode is on, refresh the forecast before checking the weather.
+            refresh_forecast(settings).await;
             // fetch weather data, if request fails, fallback to recovery loop.
-            match fetch_weather::openweathermap(&settings.key, &settings.city, &settings.country)
-                .await
-            {
-                Ok(response) => {
+            match fetch_observation(settings).await {
+                Ok(observation) => {
                     if settings.recovery_mode {
                         settings.current_loop = true;
                         settings.recovery_mode = false
                     }
                     // If daytime mode is on, change wallpaper on sunrise and sunset.
                     if settings.modes.contains(&Mode::Daytime) {
-                        settings.check_daytime_mode(&response);
+                        settings.check_daytime_mode(&observation);
                     }
-                    // If weather mode is on, change wallpaper when weather changes.
+                    // If weather mode is on, change wallpaper when weather changes. Weather
+                    // effects alone redraw the wallpaper on screen.
                     if settings.modes.contains(&Mode::Weather) {
-                        settings.check_weather_mode(&response);
+                        settings.check_weather_mode(&observation);
+                    } else if settings.effects.enabled {
+                        settings.check_effects(&observation);
+                    }
+                    // If blend mode is on, re-render when the sun has moved far enough.
+                    if settings.modes.contains(&Mode::Blend) {
+                        settings.check_blend_mode(&observation);
                     }
+                    settings.observation = Some(observation);
+                }
+                Err(error) => {
+                    settings.weather_failed("weather request", &error);
+                    settings.recovery_mode = true
                 }
-                Err(_) => settings.recovery_mode = true,
             }
         }
         // If a change has been detected in any of the modes, change wallpaper.
-        if settings.current_loop {
-            settings.set_wallpaper().ok();
-            settings.current_loop = false
+        if !change_wallpaper(settings) && settings.overlay.enabled && !settings.paused {
+            // Redraw the overlay when the clock or weather values have changed.
+            settings.check_overlay();
         }
-        // sleep for a minute
-        thread::sleep(time::Duration::from_secs(60))
+        // Wait a minute, handling D-Bus commands in the meantime.
+        wait(settings, &mut service).await
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
+// Change the wallpaper if any of the modes asked for it. While paused the change is held
+// back until resumed.
+fn change_wallpaper(settings: &mut Settings) -> bool {
+    if !settings.current_loop || settings.paused {
+        return false;
+    }
+    if let Err(error) = settings.set_wallpaper() {
+        eprintln!(
+            "fehther: setting wallpaper failed: {}",
+            error::describe(&error, &settings.key)
+        );
+    }
+    settings.current_loop = false;
+    true
+}
+
+// Sleep until the next poll. Commands are handled as they arrive, without moving the
+// poll, so the timers counting polls keep their pace.
+async fn wait(settings: &mut Settings, service: &mut Option<dbus::Service>) {
+    let deadline = time::Instant::now() + time::Duration::from_secs(60);
+    publish(settings, service.as_ref()).await;
+    if let Some(service) = service {
+        while let Ok(Some((command, reply))) =
+            time::timeout_at(deadline, service.requests.recv()).await
+        {
+            let result = handle_command(settings, command);
+            change_wallpaper(settings);
+            reply
+                .send(result.map_err(|error| format!("{:#}", error)))
+                .ok();
+            publish(settings, Some(service)).await;
+        }
+    }
+    time::sleep_until(deadline).await
+}
+
+// Make the current state available to `fehther status` and D-Bus clients.
+async fn publish(settings: &Settings, service: Option<&dbus::Service>) {
+    let state = status::State::from_settings(settings);
+    if let Err(error) = status::write(&status::fetch_state_path(), &state) {
+        eprintln!("fehther: writing state failed: {:#}", error);
+    }
+    if let Some(Err(error)) = match service {
+        Some(service) => Some(service.publish(settings).await),
+        None => None,
+    } {
+        eprintln!("fehther: D-Bus update failed: {:#}", error);
+    }
+}
+
+fn handle_command(settings: &mut Settings, command: dbus::Command) -> Result<()> {
+    println!("fehther: {} requested over D-Bus", command);
+    match command {
+        dbus::Command::Next => settings.current_loop = true,
+        dbus::Command::Previous => settings.previous_wallpaper()?,
+        dbus::Command::Pause => settings.paused = true,
+        dbus::Command::Resume => settings.paused = false,
+        dbus::Command::Reload => reload(settings)?,
+        dbus::Command::SetOverride(folder) => settings.set_override(&folder)?,
+    }
+    Ok(())
+}
+
+// Read config.ini again and show a wallpaper picked with it. What is on screen, the last
+// weather and what was set at runtime are kept.
+fn reload(settings: &mut Settings) -> Result<()> {
     let config_path = config::fetch_config_path()?;
+    let mut reloaded =
+        config::fetch_config(config_path.clone()).context(FehtherError::Config(config_path))?;
+    reloaded.current_images = std::mem::take(&mut settings.current_images);
+    reloaded.shown = std::mem::take(&mut settings.shown);
+    reloaded.history = std::mem::take(&mut settings.history);
+    std::mem::swap(&mut reloaded.daytime, &mut settings.daytime);
+    std::mem::swap(&mut reloaded.weather, &mut settings.weather);
+    reloaded.observation = settings.observation.take();
+    reloaded.paused = settings.paused;
+    reloaded.override_folder = settings.override_folder.take();
+    *settings = reloaded;
+    Ok(())
+}
+
+// Resolve an IP location to coordinates, retried on every poll until it succeeds.
+async fn resolve_location(settings: &mut Settings) {
+    if settings.location == Location::Ip {
+        match location::geolocate(&settings.http, &settings.geolocation_url).await {
+            Ok(location) => {
+                println!("fehther: located at {}", location);
+                settings.location = location;
+            }
+            Err(error) => eprintln!("fehther: geolocation failed: {:#}", error),
+        }
+    }
+}
+
+// Follow the Home Assistant control entities, an automation can show the next wallpaper
+// or pause fehther.
+async fn check_home_assistant(settings: &mut Settings) {
+    let Some(home_assistant) = &mut settings.home_assistant else {
+        return;
+    };
+    match home_assistant.control(&settings.http).await {
+        Ok(control) => {
+            if control.next {
+                println!("fehther: next wallpaper requested by Home Assistant");
+                settings.current_loop = true;
+            }
+            if let Some(paused) = control.paused {
+                println!(
+                    "fehther: {} by Home Assistant",
+                    if paused { "paused" } else { "resumed" }
+                );
+                settings.paused = paused;
+            }
+        }
+        Err(error) => eprintln!(
+            "fehther: Home Assistant control failed: {}",
+            error::describe(&error, &home_assistant.token)
+        ),
+    }
+}
+
+// Offset from UTC for the overlay clock. Only OpenWeatherMap reports it, for other sources
+// it is looked up for followed or configured coordinates.
+fn fetch_utc_offset(settings: &Settings) -> Option<i64> {
+    let timezone = match (&settings.timezone, &settings.location) {
+        (Some(timezone), _) => timezone.clone(),
+        (None, Location::Coordinates { lat, lon }) => utils::fetch_timezone(*lat, *lon),
+        _ => return None,
+    };
+    utils::fetch_utc_offset(&timezone, utils::now_secs() as i64)
+}
+
+// Fetch the forecast when its timer is up, failures are handled like weather failures.
+async fn refresh_forecast(settings: &mut Settings) {
+    if !settings.check_forecast_timer() {
+        return;
+    }
+    match fetch_weather::openweathermap_forecast(&settings.http, &settings.key, &settings.location)
+        .await
+    {
+        Ok(forecast) => settings.forecast = forecast.entries(),
+        Err(error) => settings.weather_failed("forecast", &error),
+    }
+}
+
+// Get the current weather from the configured source. Local sources rarely know sunrise
+// and sunset, those are calculated for configured or followed coordinates.
+async fn fetch_observation(settings: &mut Settings) -> Result<Observation> {
+    if let WeatherSource::OpenWeatherMap = settings.weather_source {
+        return Ok(fetch_weather::openweathermap(
+            &settings.http,
+            &settings.key,
+            &settings.location,
+        )
+        .await?
+        .observation());
+    }
+    let mut observation = match (&settings.weather_source, &settings.home_assistant) {
+        (WeatherSource::HomeAssistant, Some(home_assistant)) => {
+            home_assistant.observation(&settings.http).await?
+        }
+        _ => settings.weather_source.read()?,
+    };
+    if let Some(timezone) = fetch_utc_offset(settings) {
+        observation.timezone = timezone;
+    }
+    if let Location::Coordinates { lat, lon } = settings.location {
+        observation.coordinates = observation.coordinates.or(Some((lat, lon)));
+        if observation.sunrise.is_none() || observation.sunset.is_none() {
+            let sun_times = utils::fetch_sun_times(utils::now_secs() as i64, lat, lon);
+            observation.sunrise = sun_times.map(|(sunrise, _)| sunrise);
+            observation.sunset = sun_times.map(|(_, sunset)| sunset);
+        }
+    }
+    Ok(observation)
+}
+
+// Resolve the current state once and print the folder fallback chain.
+async fn explain(settings: &mut Settings) -> Result<(), anyhow::Error> {
+    resolve_location(settings).await;
+    refresh_forecast(settings).await;
+    if settings.modes.contains(&Mode::Daytime) || settings.modes.contains(&Mode::Weather) {
+        match fetch_observation(settings).await {
+            Ok(observation) => {
+                if settings.modes.contains(&Mode::Daytime) {
+                    settings.check_daytime_mode(&observation);
+                }
+                if settings.modes.contains(&Mode::Weather) {
+                    settings.check_weather_mode(&observation);
+                }
+            }
+            Err(error) => {
+                settings.weather_failed("weather request", &error);
+                settings.recovery_mode = true
+            }
+        }
+    }
+    print!("{}", settings.explain(settings.open_library().as_ref()));
+    Ok(())
+}
+
+// Build or update the tag index for the wallpaper folder.
+fn index(settings: &Settings) -> Result<(), anyhow::Error> {
+    let mut index = tags::TagIndex::load(&settings.index_file);
+    let (updated, removed) = index.refresh(std::path::Path::new(&settings.path), &settings.scan);
+    index.save(&settings.index_file)?;
+    println!(
+        "indexed {} images ({} updated, {} removed) into {}",
+        index.images.len(),
+        updated,
+        removed,
+        settings.index_file.display()
+    );
+    Ok(())
+}
+
+// Inspect and maintain the wallpaper library.
+fn library_command(settings: &Settings, args: &[String]) -> Result<(), anyhow::Error> {
+    let library = Library::open(&settings.library_file)?;
+    match args.first().map(String::as_str) {
+        Some("stats") => {
+            println!("library: {}", settings.library_file.display());
+            print!("{}", library.stats()?);
+        }
+        Some("rescan") => {
+            let (updated, removed) =
+                library.rescan(std::path::Path::new(&settings.path), &settings.scan)?;
+            println!(
+                "{} images in library ({} updated, {} removed)",
+                library.stats()?.images,
+                updated,
+                removed
+            );
+        }
+        Some("tag") if args.len() >= 2 => {
+            let image = fs::canonicalize(&args[1])?.display().to_string();
+            let custom_tags: Vec<String> =
+                args[2..].iter().map(|t| tags::normalize_tag(t)).collect();
+            if !library.set_custom_tags(&image, &custom_tags)? {
+                return Err(anyhow::anyhow!("not in library: {}", image));
+            }
+        }
+        _ => {
+            return Err(anyhow::anyhow!(
+                "usage: fehther library stats | rescan | tag <image> [tags...]"
+            ));
+        }
+    }
+    Ok(())
+}
+
+// List places matching a query, with the settings to use them.
+async fn locate(settings: &Settings, query: &[String]) -> Result<(), anyhow::Error> {
+    if query.is_empty() {
+        return Err(anyhow::anyhow!("usage: fehther locate <query>"));
+    }
+    let matches = fetch_weather::geocode(&settings.http, &settings.key, &query.join(" ")).await?;
+    if matches.is_empty() {
+        println!("no places found");
+    }
+    for place in matches {
+        let state = place.state.map(|state| format!(", {}", state));
+        println!(
+            "{}{}, {}\n    lat = {:.4}\n    lon = {:.4}",
+            place.name,
+            state.unwrap_or_default(),
+            place.country,
+            place.lat,
+            place.lon
+        );
+    }
+    Ok(())
+}
+
+// Print an error with a hint and exit with the code of its class.
+fn exit(error: anyhow::Error, key: &ApiKey) -> ! {
+    eprintln!("fehther: {}", error::describe(&error, key));
+    std::process::exit(error::exit_code(&error))
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    // Status only reads the state of the running fehther, it works without a config.
+    if args.get(1).is_some_and(|command| command == "status") {
+        if let Err(error) = status::status_command(&args[2..]) {
+            exit(error, &ApiKey::default())
+        }
+        return;
+    }
     // Load configuration
-    let mut settings: Settings = config::fetch_config(config_path)?;
-    // Start loop
-    wallpaper_manager_loop(&mut settings).await
+    let mut settings: Settings = match config::fetch_config_path().and_then(|config_path| {
+        config::fetch_config(config_path.clone()).context(FehtherError::Config(config_path))
+    }) {
+        Ok(settings) => settings,
+        Err(error) => exit(error, &ApiKey::default()),
+    };
+    let result = match args.get(1).map(String::as_str) {
+        Some("explain") => explain(&mut settings).await,
+        Some("index") => index(&settings),
+        Some("library") => library_command(&settings, &args[2..]),
+        Some("locate") => locate(&settings, &args[2..]).await,
+        // Start loop
+        None => wallpaper_manager_loop(&mut settings).await,
+        Some(command) => Err(anyhow::anyhow!("unknown command: {}", command)),
+    };
+    if let Err(error) = result {
+        exit(error, &settings.key)
+    }
 }
@@ -0,0 +1,345 @@
+use crate::effects;
+use crate::fetch_weather::Observation;
+use crate::transition;
+use crate::types::position::Position;
+use crate::types::weathertype::WeatherType;
+use crate::utils;
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont, point};
+use anyhow::{Context, Result, anyhow};
+use image::RgbImage;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+// Fonts tried when no font is configured.
+const FONT_PATHS: [&str; 5] = [
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/noto/NotoSans-Regular.ttf",
+    "/usr/share/fonts/truetype/noto/NotoSans-Regular.ttf",
+];
+
+// Text overlay settings, parsed from the [overlay] section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverlaySettings {
+    pub enabled: bool,
+    // Text with placeholders, see format_text. Lines are separated by \n.
+    pub format: String,
+    pub position: Position,
+    pub font: Option<PathBuf>,
+    // Font size in pixels on a 1080 pixel high image, scaled with the image height.
+    pub size: f32,
+    // RGBA, the alpha channel sets the text opacity.
+    pub colour: [u8; 4],
+    pub shadow: bool,
+}
+
+impl Default for OverlaySettings {
+    fn default() -> Self {
+        OverlaySettings {
+            enabled: false,
+            format: "{icon} {temp}  {condition}\n{location}\n↑ {sunrise}  ↓ {sunset}".to_string(),
+            position: Position::BottomRight,
+            font: None,
+            size: 28.0,
+            colour: [255, 255, 255, 230],
+            shadow: true,
+        }
+    }
+}
+
+// Parse a colour as #rrggbb or #rrggbbaa.
+pub fn parse_colour(colour: &str) -> Result<[u8; 4]> {
+    let hex = colour.trim().trim_start_matches('#');
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("not a colour: {}", colour));
+    }
+    let mut rgba = [255u8; 4];
+    for (i, value) in rgba.iter_mut().enumerate().take(hex.len() / 2) {
+        *value = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+    }
+    Ok(rgba)
+}
+
+// Symbol for the weather condition, shown by {icon}.
+pub fn icon(weather: &WeatherType) -> &'static str {
+    match weather.family() {
+        WeatherType::Clear => "☀",
+        WeatherType::FewClouds | WeatherType::ScatteredClouds => "⛅",
+        WeatherType::BrokenClouds | WeatherType::OvercastClouds => "☁",
+        WeatherType::Drizzle | WeatherType::Rain => "☂",
+        WeatherType::Snow => "❄",
+        WeatherType::Thunder => "⚡",
+        WeatherType::Mist => "≡",
+        _ => "",
+    }
+}
+
+// Local wall clock time as HH:MM.
+fn clock(timestamp: i64, timezone: i64) -> String {
+    let minutes = (timestamp + timezone).rem_euclid(86400) / 60;
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+// Fill in the placeholders {temp}, {feels_like}, {humidity}, {condition}, {icon},
+// {location}, {sunrise}, {sunset} and {time}. Missing values are left empty.
+pub fn format_text(format: &str, observation: &Observation, now: i64) -> String {
+    let degrees = |value: Option<f64>| {
+        value
+            .map(|value| format!("{}°C", value.round() + 0.0))
+            .unwrap_or_default()
+    };
+    let condition = match observation.description.is_empty() {
+        true => observation
+            .weather
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_default(),
+        false => observation.description.clone(),
+    };
+    let text = format
+        .replace("{temp}", &degrees(observation.temperature))
+        .replace("{feels_like}", &degrees(observation.feels_like))
+        .replace(
+            "{humidity}",
+            &observation
+                .humidity
+                .map(|humidity| format!("{}%", humidity.round()))
+                .unwrap_or_default(),
+        )
+        .replace("{condition}", &condition)
+        .replace("{icon}", observation.weather.as_ref().map_or("", icon))
+        .replace("{location}", &observation.location)
+        .replace(
+            "{sunrise}",
+            &observation
+                .sunrise
+                .map(|sunrise| clock(sunrise, observation.timezone))
+                .unwrap_or_default(),
+        )
+        .replace(
+            "{sunset}",
+            &observation
+                .sunset
+                .map(|sunset| clock(sunset, observation.timezone))
+                .unwrap_or_default(),
+        )
+        .replace("{time}", &clock(now, observation.timezone));
+    text.lines().map(str::trim).collect::<Vec<_>>().join("\n")
+}
+
+// Load the configured font, or the first common system font that exists.
+pub fn load_font(font: Option<&Path>) -> Result<FontVec> {
+    if let Some(font) = font {
+        let data = fs::read(font).with_context(|| format!("Could not read {}", font.display()))?;
+        return FontVec::try_from_vec(data)
+            .with_context(|| format!("Invalid font: {}", font.display()));
+    }
+    FONT_PATHS
+        .iter()
+        .find_map(|path| fs::read(path).ok())
+        .and_then(|data| FontVec::try_from_vec(data).ok())
+        .ok_or_else(|| anyhow!("no system font found, set font in [overlay]"))
+}
+
+fn line_width(font: &FontVec, scale: PxScale, line: &str) -> f32 {
+    let scaled = font.as_scaled(scale);
+    let mut previous = None;
+    let mut width = 0.0;
+    for c in line.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            width += scaled.kern(previous, id);
+        }
+        width += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    width
+}
+
+// Draw a single line of text with its top left corner at (x, y).
+fn draw_line(
+    image: &mut RgbImage,
+    font: &FontVec,
+    scale: PxScale,
+    line: &str,
+    (x, y): (f32, f32),
+    colour: [u8; 3],
+    alpha: f32,
+) {
+    let scaled = font.as_scaled(scale);
+    let mut caret = point(x, y + scaled.ascent());
+    let mut previous = None;
+    for c in line.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret.x += scaled.kern(previous, id);
+        }
+        let glyph = id.with_scale_and_position(scale, caret);
+        caret.x += scaled.h_advance(id);
+        previous = Some(id);
+        if let Some(outlined) = font.outline_glyph(glyph) {
+            let bounds = outlined.px_bounds();
+            outlined.draw(|gx, gy, coverage| {
+                effects::mix(
+                    image,
+                    bounds.min.x as i64 + gx as i64,
+                    bounds.min.y as i64 + gy as i64,
+                    colour,
+                    coverage * alpha,
+                )
+            });
+        }
+    }
+}
+
+// Draw the text onto the image, one margin away from the configured edges.
+pub fn draw_text(image: &mut RgbImage, text: &str, font: &FontVec, settings: &OverlaySettings) {
+    let scale = PxScale::from(settings.size * image.height() as f32 / 1080.0);
+    let scaled = font.as_scaled(scale);
+    let line_height = scaled.height() + scaled.line_gap();
+    let lines: Vec<&str> = text.lines().collect();
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let margin = scale.y;
+    let block_height = line_height * lines.len() as f32;
+    let top = match settings.position {
+        Position::TopLeft | Position::TopRight => margin,
+        Position::BottomLeft | Position::BottomRight => height - margin - block_height,
+        Position::Center => (height - block_height) / 2.0,
+    };
+    let [red, green, blue, alpha] = settings.colour;
+    let shadow_offset = (scale.y / 16.0).max(1.0);
+    for (i, line) in lines.iter().enumerate() {
+        let line_width = line_width(font, scale, line);
+        let x = match settings.position {
+            Position::TopLeft | Position::BottomLeft => margin,
+            Position::TopRight | Position::BottomRight => width - margin - line_width,
+            Position::Center => (width - line_width) / 2.0,
+        };
+        let y = top + line_height * i as f32;
+        if settings.shadow {
+            let origin = (x + shadow_offset, y + shadow_offset);
+            draw_line(
+                image,
+                font,
+                scale,
+                line,
+                origin,
+                [0, 0, 0],
+                0.6 * alpha as f32 / 255.0,
+            );
+        }
+        draw_line(
+            image,
+            font,
+            scale,
+            line,
+            (x, y),
+            [red, green, blue],
+            alpha as f32 / 255.0,
+        );
+    }
+}
+
+// Draw the text onto an image and write it to the cache.
+pub fn render_cached(
+    image: &str,
+    size: Option<(u32, u32)>,
+    text: &str,
+    font: &FontVec,
+    settings: &OverlaySettings,
+    cache_dir: &Path,
+) -> Result<String> {
+    let mut hasher = DefaultHasher::new();
+    image.hash(&mut hasher);
+    utils::modified_secs(Path::new(image)).hash(&mut hasher);
+    (size, text, format!("{:?}", settings)).hash(&mut hasher);
+    let file = cache_dir.join(format!("{:016x}.jpg", hasher.finish()));
+    if file.is_file() {
+        return Ok(file.display().to_string());
+    }
+
+    let size = match size {
+        Some(size) => size,
+        None => image::image_dimensions(image)?,
+    };
+    let mut canvas = transition::load_fitted(Path::new(image), size)?;
+    draw_text(&mut canvas, text, font, settings);
+    fs::create_dir_all(cache_dir)?;
+    canvas.save(&file)?;
+    Ok(file.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    fn observation() -> Observation {
+        Observation {
+            weather: Some(WeatherType::Rain),
+            description: "light rain".to_string(),
+            temperature: Some(-0.4),
+            feels_like: Some(-3.6),
+            humidity: Some(81.0),
+            location: "Amsterdam, NL".to_string(),
+            sunrise: Some(1_700_000_000),
+            sunset: Some(1_700_030_000),
+            timezone: 3600,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_format_text() {
+        let text = format_text(
+            "{icon} {temp} ({feels_like}) {humidity}\n{condition} in {location}\n{sunrise}-{sunset} {time}",
+            &observation(),
+            1_700_010_000,
+        );
+        assert_eq!(
+            text,
+            "☂ 0°C (-4°C) 81%\nlight rain in Amsterdam, NL\n23:13-07:33 02:00"
+        );
+
+        let observation = Observation {
+            temperature: None,
+            description: String::new(),
+            ..observation()
+        };
+        assert_eq!(format_text("{temp} {condition}", &observation, 0), "rain");
+    }
+
+    #[test]
+    fn test_parse_colour() {
+        assert_eq!(parse_colour("#ff8000").unwrap(), [255, 128, 0, 255]);
+        assert_eq!(parse_colour("10203040").unwrap(), [16, 32, 48, 64]);
+        assert!(parse_colour("#fff").is_err());
+        assert!(parse_colour("#gg0000").is_err());
+    }
+
+    #[test]
+    fn test_draw_text_position() {
+        // Rendering needs a system font, which minimal build environments may not have.
+        let Ok(font) = load_font(None) else {
+            return;
+        };
+        let settings = OverlaySettings {
+            enabled: true,
+            position: Position::BottomRight,
+            shadow: false,
+            ..Default::default()
+        };
+        let background = RgbImage::from_pixel(320, 180, Rgb([0, 0, 0]));
+        let mut image = background.clone();
+        draw_text(&mut image, "12°C", &font, &settings);
+        let changed: Vec<(u32, u32)> = image
+            .enumerate_pixels()
+            .filter(|(x, y, pixel)| *pixel != background.get_pixel(*x, *y))
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert!(!changed.is_empty());
+        assert!(changed.iter().all(|(x, y)| *x > 160 && *y > 90));
+    }
+}
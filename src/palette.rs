@@ -0,0 +1,430 @@
+use crate::utils;
+use anyhow::{Context, Result, anyhow};
+use image::RgbImage;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Colour palette settings, parsed from the [palette] section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteSettings {
+    pub enabled: bool,
+    // Number of colours, color0 up to colorN-1 in the outputs.
+    pub colours: usize,
+    pub formats: Vec<Format>,
+    // Folder the palette files are written to.
+    pub output: PathBuf,
+    // Folder with templates, each is written to the output folder under the same name.
+    pub template_dir: Option<PathBuf>,
+}
+
+impl Default for PaletteSettings {
+    fn default() -> Self {
+        PaletteSettings {
+            enabled: false,
+            colours: 8,
+            formats: vec![Format::Json, Format::Xresources, Format::Css, Format::Shell],
+            output: utils::fetch_cache_dir().join("palette"),
+            template_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Xresources,
+    Css,
+    Shell,
+}
+
+impl Format {
+    pub fn from_string(format: &str) -> Result<Format> {
+        match format.trim().to_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "xresources" => Ok(Format::Xresources),
+            "css" => Ok(Format::Css),
+            "shell" | "sh" => Ok(Format::Shell),
+            _ => Err(anyhow!("not a known palette format: {}", format)),
+        }
+    }
+
+    fn file_name(&self) -> &'static str {
+        match self {
+            Format::Json => "palette.json",
+            Format::Xresources => "palette.Xresources",
+            Format::Css => "palette.css",
+            Format::Shell => "palette.sh",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Colour(pub [u8; 3]);
+
+impl fmt::Display for Colour {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [r, g, b] = self.0;
+        write!(f, "#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+impl Serialize for Colour {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl Colour {
+    // Relative luminance between 0.0 and 1.0, on the gamma encoded values.
+    fn luminance(&self) -> f64 {
+        let [r, g, b] = self.0.map(|value| value as f64 / 255.0);
+        0.2126 * r + 0.7152 * g + 0.0722 * b
+    }
+
+    fn saturation(&self) -> f64 {
+        let max = *self.0.iter().max().unwrap() as f64;
+        let min = *self.0.iter().min().unwrap() as f64;
+        match max {
+            0.0 => 0.0,
+            _ => (max - min) / max,
+        }
+    }
+
+    // Move towards another colour, 0.0 keeps this colour.
+    fn mix(&self, other: Colour, amount: f64) -> Colour {
+        Colour(std::array::from_fn(|i| {
+            (self.0[i] as f64 + (other.0[i] as f64 - self.0[i] as f64) * amount).round() as u8
+        }))
+    }
+}
+
+// Colours of a wallpaper. The same image always gives the same palette.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Palette {
+    pub wallpaper: String,
+    // Whether the image is dark overall, background and foreground follow it.
+    pub dark: bool,
+    // Colour covering most of the image.
+    pub dominant: Colour,
+    // Most vivid colour that still covers a fair part of the image.
+    pub accent: Colour,
+    pub background: Colour,
+    pub foreground: Colour,
+    // Most common colours first.
+    pub colors: Vec<Colour>,
+}
+
+// Cut the pixels into boxes of similar colours, splitting the box with the widest channel
+// range at its median until there are enough boxes. Returns the average colour of each
+// box with its pixel count, most common first.
+fn median_cut(pixels: Vec<[u8; 3]>, count: usize) -> Vec<(Colour, usize)> {
+    let range = |pixels: &[[u8; 3]]| {
+        (0..3)
+            .map(|channel| {
+                let values = pixels.iter().map(|pixel| pixel[channel]);
+                let max = values.clone().max().unwrap_or(0);
+                let min = values.min().unwrap_or(0);
+                (max - min, channel)
+            })
+            // Ties go to the first channel.
+            .max_by_key(|&(range, channel)| (range, std::cmp::Reverse(channel)))
+            .unwrap()
+    };
+    let mut boxes = vec![pixels];
+    while boxes.len() < count {
+        let Some((index, (width, channel))) = boxes
+            .iter()
+            .map(|pixels| range(pixels))
+            .enumerate()
+            .max_by_key(|&(index, (width, _))| (width, std::cmp::Reverse(index)))
+        else {
+            break;
+        };
+        // Every box holds a single colour.
+        if width == 0 {
+            break;
+        }
+        let mut lower = boxes.remove(index);
+        lower.sort_by_key(|pixel| pixel[channel]);
+        let upper = lower.split_off(lower.len() / 2);
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+    // Cutting at the median can split a single colour over two boxes, those are merged.
+    let mut colours: Vec<(Colour, usize)> = Vec::new();
+    for pixels in boxes.iter().filter(|pixels| !pixels.is_empty()) {
+        let sum = pixels.iter().fold([0usize; 3], |sum, pixel| {
+            std::array::from_fn(|i| sum[i] + pixel[i] as usize)
+        });
+        let average = Colour(sum.map(|value| (value as f64 / pixels.len() as f64).round() as u8));
+        match colours.iter_mut().find(|(colour, _)| *colour == average) {
+            Some((_, count)) => *count += pixels.len(),
+            None => colours.push((average, pixels.len())),
+        }
+    }
+    colours.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    colours
+}
+
+// Extract the palette from an image, scaled down first to keep it quick.
+pub fn extract(image: &RgbImage, wallpaper: &str, count: usize) -> Palette {
+    let pixels: Vec<[u8; 3]> = image.pixels().map(|pixel| pixel.0).collect();
+    let total = pixels.len().max(1) as f64;
+    let luminance = pixels
+        .iter()
+        .map(|pixel| Colour(*pixel).luminance())
+        .sum::<f64>()
+        / total;
+    let colours = median_cut(pixels, count.max(1));
+    let dominant = colours
+        .first()
+        .map_or(Colour([0, 0, 0]), |(colour, _)| *colour);
+    let accent = colours
+        .iter()
+        .skip(1)
+        .map(|(colour, pixels)| {
+            (
+                *colour,
+                colour.saturation() * (*pixels as f64 / total).sqrt(),
+            )
+        })
+        .fold(
+            None,
+            |best: Option<(Colour, f64)>, (colour, score)| match best {
+                Some((_, best_score)) if best_score >= score => best,
+                _ => Some((colour, score)),
+            },
+        )
+        .map_or(dominant, |(colour, _)| colour);
+    let mut colors: Vec<Colour> = colours.iter().map(|(colour, _)| *colour).collect();
+    // Images with few colours repeat them, so there are always as many as configured.
+    let found = colors.len().max(1);
+    colors = (0..count.max(1))
+        .map(|i| colors.get(i % found).copied().unwrap_or(dominant))
+        .collect();
+    let by_luminance = |a: &&Colour, b: &&Colour| a.luminance().total_cmp(&b.luminance());
+    let darkest = *colors.iter().min_by(by_luminance).unwrap();
+    let lightest = *colors.iter().max_by(by_luminance).unwrap();
+    let dark = luminance < 0.5;
+    let (background, foreground) = match dark {
+        true => (darkest, lightest.mix(Colour([255, 255, 255]), 0.5)),
+        false => (lightest, darkest.mix(Colour([0, 0, 0]), 0.5)),
+    };
+    Palette {
+        wallpaper: wallpaper.to_string(),
+        dark,
+        dominant,
+        accent,
+        background,
+        foreground,
+        colors,
+    }
+}
+
+impl Palette {
+    // Names and values used by all text formats and templates.
+    pub fn placeholders(&self) -> Vec<(String, String)> {
+        let mut placeholders = vec![
+            ("wallpaper".to_string(), self.wallpaper.clone()),
+            (
+                "mode".to_string(),
+                match self.dark {
+                    true => "dark".to_string(),
+                    false => "light".to_string(),
+                },
+            ),
+            ("background".to_string(), self.background.to_string()),
+            ("foreground".to_string(), self.foreground.to_string()),
+            ("dominant".to_string(), self.dominant.to_string()),
+            ("accent".to_string(), self.accent.to_string()),
+        ];
+        for (i, colour) in self.colors.iter().enumerate() {
+            placeholders.push((format!("color{}", i), colour.to_string()));
+        }
+        placeholders
+    }
+
+    pub fn format(&self, format: Format) -> String {
+        let placeholders = self.placeholders();
+        let colours = placeholders.iter().skip(2);
+        match format {
+            Format::Json => serde_json::to_string_pretty(self).unwrap() + "\n",
+            Format::Xresources => {
+                let mut text = format!("! Generated by fehther from {}\n", self.wallpaper);
+                for (name, value) in colours {
+                    match name.as_str() {
+                        "dominant" | "accent" => text += &format!("fehther.{}: {}\n", name, value),
+                        _ => text += &format!("*.{}: {}\n", name, value),
+                    }
+                }
+                text
+            }
+            Format::Css => {
+                let mut text = format!(":root {{\n  --wallpaper: url(\"{}\");\n", self.wallpaper);
+                for (name, value) in colours {
+                    text += &format!("  --{}: {};\n", name, value);
+                }
+                text + "}\n"
+            }
+            Format::Shell => placeholders
+                .iter()
+                .map(|(name, value)| format!("{}='{}'\n", name, value.replace('\'', "'\\''")))
+                .collect(),
+        }
+    }
+
+    // Replace {name} placeholders, unknown ones are left as they are.
+    pub fn render_template(&self, template: &str) -> String {
+        self.placeholders()
+            .iter()
+            .fold(template.to_string(), |text, (name, value)| {
+                text.replace(&format!("{{{}}}", name), value)
+            })
+    }
+}
+
+// Extract the palette of an image file and write all configured formats and templates.
+pub fn update(image: &Path, settings: &PaletteSettings) -> Result<Palette> {
+    let thumbnail = image::open(image)
+        .with_context(|| format!("Could not open {}", image.display()))?
+        .thumbnail(64, 64)
+        .to_rgb8();
+    let palette = extract(&thumbnail, &image.display().to_string(), settings.colours);
+    write(&palette, settings)?;
+    Ok(palette)
+}
+
+fn write(palette: &Palette, settings: &PaletteSettings) -> Result<()> {
+    fs::create_dir_all(&settings.output)
+        .with_context(|| format!("Could not create {}", settings.output.display()))?;
+    for format in &settings.formats {
+        fs::write(
+            settings.output.join(format.file_name()),
+            palette.format(*format),
+        )?;
+    }
+    let Some(template_dir) = &settings.template_dir else {
+        return Ok(());
+    };
+    let mut templates: Vec<PathBuf> = fs::read_dir(template_dir)
+        .with_context(|| format!("Could not read {}", template_dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    templates.sort();
+    for template in templates {
+        let text = fs::read_to_string(&template)
+            .with_context(|| format!("Could not read template {}", template.display()))?;
+        fs::write(
+            settings.output.join(template.file_name().unwrap()),
+            palette.render_template(&text),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    // Three quarters dark blue, a quarter orange.
+    fn test_image() -> RgbImage {
+        RgbImage::from_fn(16, 16, |x, _| match x < 12 {
+            true => Rgb([22, 30, 70]),
+            false => Rgb([240, 140, 20]),
+        })
+    }
+
+    #[test]
+    fn test_extract() {
+        let palette = extract(&test_image(), "/wallpapers/night/lake.jpg", 4);
+        assert!(palette.dark);
+        assert_eq!(palette.dominant, Colour([22, 30, 70]));
+        assert_eq!(palette.accent, Colour([240, 140, 20]));
+        assert_eq!(palette.background, Colour([22, 30, 70]));
+        assert_eq!(palette.colors.len(), 4);
+        // The same image always gives the same palette.
+        assert_eq!(
+            palette,
+            extract(&test_image(), "/wallpapers/night/lake.jpg", 4)
+        );
+        let light = extract(
+            &RgbImage::from_pixel(4, 4, Rgb([230, 230, 220])),
+            "light.png",
+            3,
+        );
+        assert!(!light.dark);
+        assert_eq!(light.colors, vec![Colour([230, 230, 220]); 3]);
+        assert_eq!(light.foreground, Colour([115, 115, 110]));
+    }
+
+    fn palette() -> Palette {
+        Palette {
+            wallpaper: "/wallpapers/it's.jpg".to_string(),
+            dark: true,
+            dominant: Colour([22, 30, 70]),
+            accent: Colour([240, 140, 20]),
+            background: Colour([22, 30, 70]),
+            foreground: Colour([248, 198, 138]),
+            colors: vec![Colour([22, 30, 70]), Colour([240, 140, 20])],
+        }
+    }
+
+    #[test]
+    fn test_formats() {
+        let palette = palette();
+        assert_eq!(
+            palette.format(Format::Xresources),
+            "! Generated by fehther from /wallpapers/it's.jpg\n\
+             *.background: #161e46\n*.foreground: #f8c68a\n\
+             fehther.dominant: #161e46\nfehther.accent: #f08c14\n\
+             *.color0: #161e46\n*.color1: #f08c14\n"
+        );
+        assert_eq!(
+            palette.format(Format::Css),
+            ":root {\n  --wallpaper: url(\"/wallpapers/it's.jpg\");\n  \
+             --background: #161e46;\n  --foreground: #f8c68a;\n  --dominant: #161e46;\n  \
+             --accent: #f08c14;\n  --color0: #161e46;\n  --color1: #f08c14;\n}\n"
+        );
+        assert_eq!(
+            palette.format(Format::Shell),
+            "wallpaper='/wallpapers/it'\\''s.jpg'\nmode='dark'\nbackground='#161e46'\n\
+             foreground='#f8c68a'\ndominant='#161e46'\naccent='#f08c14'\n\
+             color0='#161e46'\ncolor1='#f08c14'\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&palette.format(Format::Json)).unwrap();
+        assert_eq!(json["accent"], "#f08c14");
+        assert_eq!(json["colors"][1], "#f08c14");
+        assert_eq!(json["dark"], true);
+        assert_eq!(
+            palette.render_template("bg={background} fg={foreground} c1={color1} {unknown}"),
+            "bg=#161e46 fg=#f8c68a c1=#f08c14 {unknown}"
+        );
+    }
+
+    #[test]
+    fn test_update() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("lake.png");
+        test_image().save(&image).unwrap();
+        let template_dir = dir.path().join("templates");
+        fs::create_dir(&template_dir).unwrap();
+        fs::write(template_dir.join("kitty.conf"), "background {background}\n").unwrap();
+        let settings = PaletteSettings {
+            enabled: true,
+            colours: 4,
+            formats: vec![Format::Json, Format::Shell],
+            output: dir.path().join("palette"),
+            template_dir: Some(template_dir),
+        };
+        update(&image, &settings).unwrap();
+        let output = |name: &str| fs::read_to_string(settings.output.join(name)).unwrap();
+        assert_eq!(output("kitty.conf"), "background #161e46\n");
+        assert!(output("palette.sh").contains("mode='dark'"));
+        assert!(output("palette.json").contains("\"accent\": \"#f08c14\""));
+        assert!(!settings.output.join("palette.css").exists());
+    }
+}
@@ -0,0 +1,456 @@
+use crate::fetch_weather::Observation;
+use crate::types::weathertype::WeatherType;
+use anyhow::{Result, anyhow};
+use std::fmt;
+
+// Numeric observations a rule can compare against.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Temp,
+    FeelsLike,
+    Humidity,
+    Pressure,
+    WindSpeed,
+    WindGust,
+    Clouds,
+    Visibility,
+    Rain,
+    Snow,
+}
+
+const FIELD_NAMES: [&str; 10] = [
+    "temp",
+    "feels_like",
+    "humidity",
+    "pressure",
+    "wind_speed",
+    "wind_gust",
+    "clouds",
+    "visibility",
+    "rain",
+    "snow",
+];
+
+impl Field {
+    fn from_string(field: &str) -> Option<Field> {
+        match field {
+            "temp" => Some(Field::Temp),
+            "feels_like" => Some(Field::FeelsLike),
+            "humidity" => Some(Field::Humidity),
+            "pressure" => Some(Field::Pressure),
+            "wind_speed" => Some(Field::WindSpeed),
+            "wind_gust" => Some(Field::WindGust),
+            "clouds" => Some(Field::Clouds),
+            "visibility" => Some(Field::Visibility),
+            "rain" => Some(Field::Rain),
+            "snow" => Some(Field::Snow),
+            _ => None,
+        }
+    }
+
+    // Observed value, None if the provider did not report it.
+    fn value(&self, observation: &Observation) -> Option<f64> {
+        match self {
+            Field::Temp => observation.temperature,
+            Field::FeelsLike => observation.feels_like,
+            Field::Humidity => observation.humidity,
+            Field::Pressure => observation.pressure,
+            Field::WindSpeed => observation.wind_speed,
+            Field::WindGust => observation.wind_gust,
+            Field::Clouds => observation.clouds,
+            Field::Visibility => observation.visibility,
+            Field::Rain => Some(observation.rain),
+            Field::Snow => Some(observation.snow),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    fn from_string(operator: &str) -> Option<Comparison> {
+        match operator {
+            "<" => Some(Comparison::Less),
+            "<=" => Some(Comparison::LessEqual),
+            ">" => Some(Comparison::Greater),
+            ">=" => Some(Comparison::GreaterEqual),
+            "=" | "==" => Some(Comparison::Equal),
+            "!=" => Some(Comparison::NotEqual),
+            _ => None,
+        }
+    }
+
+    fn compare(&self, left: f64, right: f64) -> bool {
+        match self {
+            Comparison::Less => left < right,
+            Comparison::LessEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterEqual => left >= right,
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+        }
+    }
+}
+
+// Parsed rule expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Compare(Field, Comparison, f64),
+    // Matches if the condition, or the base type of the condition, is in the list.
+    Condition(Vec<WeatherType>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    // Comparisons against values the provider did not report are false.
+    pub fn matches(&self, observation: &Observation) -> bool {
+        match self {
+            Expr::Compare(field, comparison, value) => field
+                .value(observation)
+                .is_some_and(|observed| comparison.compare(observed, *value)),
+            Expr::Condition(types) => observation.weather.as_ref().is_some_and(|weather| {
+                types.contains(weather) || types.contains(&weather.family())
+            }),
+            Expr::Not(expr) => !expr.matches(observation),
+            Expr::And(left, right) => left.matches(observation) && right.matches(observation),
+            Expr::Or(left, right) => left.matches(observation) || right.matches(observation),
+        }
+    }
+}
+
+// A named rule from the [weather-rules] section, the name is used as folder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeatherRule {
+    pub name: String,
+    pub expr: Expr,
+}
+
+impl WeatherRule {
+    pub fn parse(name: &str, source: &str) -> Result<WeatherRule> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        let expr = parser.or_expr()?;
+        if let Some(token) = parser.peek() {
+            return Err(anyhow!("unexpected {} after the end of the rule", token));
+        }
+        Ok(WeatherRule {
+            name: name.to_string(),
+            expr,
+        })
+    }
+}
+
+// Name of the first rule matching the observation, rules are checked in config order.
+pub fn evaluate<'a>(rules: &'a [WeatherRule], observation: &Observation) -> Option<&'a str> {
+    rules
+        .iter()
+        .find(|rule| rule.expr.matches(observation))
+        .map(|rule| rule.name.as_str())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Word(String),
+    Operator(String),
+    Open,
+    Close,
+    Comma,
+}
+
+// fmt trait for error messages
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(number) => write!(f, "'{}'", number),
+            Token::Word(word) => write!(f, "'{}'", word),
+            Token::Operator(operator) => write!(f, "'{}'", operator),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let starts_number = c.is_ascii_digit()
+            || ((c == '-' || c == '.') && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit()));
+        if c.is_whitespace() {
+            i += 1;
+        } else if starts_number {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            tokens.push(Token::Number(
+                number
+                    .parse()
+                    .map_err(|_| anyhow!("invalid number '{}'", number))?,
+            ));
+        } else if c.is_alphabetic() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || "_-".contains(chars[i])) {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            tokens.push(Token::Word(word.to_lowercase()));
+        } else if "<>=!".contains(c) {
+            let start = i;
+            while i < chars.len() && "<>=!".contains(chars[i]) {
+                i += 1;
+            }
+            tokens.push(Token::Operator(chars[start..i].iter().collect()));
+        } else {
+            tokens.push(match c {
+                '(' => Token::Open,
+                ')' => Token::Close,
+                ',' => Token::Comma,
+                _ => return Err(anyhow!("unexpected character '{}'", c)),
+            });
+            i += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+// Recursive descent parser, `and` binds stronger than `or`.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| anyhow!("unexpected end of rule"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word == keyword)
+    }
+
+    fn or_expr(&mut self) -> Result<Expr> {
+        let mut expr = self.and_expr()?;
+        while self.is_keyword("or") {
+            self.position += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr> {
+        let mut expr = self.unary()?;
+        while self.is_keyword("and") {
+            self.position += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.is_keyword("not") {
+            self.position += 1;
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        match self.next()? {
+            Token::Open => {
+                let expr = self.or_expr()?;
+                match self.next()? {
+                    Token::Close => Ok(expr),
+                    token => Err(anyhow!("expected ')', found {}", token)),
+                }
+            }
+            Token::Word(word) if word == "condition" => self.condition(),
+            Token::Word(word) => {
+                let field = Field::from_string(&word).ok_or_else(|| {
+                    anyhow!(
+                        "unknown field '{}', expected condition or one of {}",
+                        word,
+                        FIELD_NAMES.join(", ")
+                    )
+                })?;
+                let comparison = match self.next()? {
+                    Token::Operator(operator) => Comparison::from_string(&operator)
+                        .ok_or_else(|| anyhow!("unknown operator '{}'", operator))?,
+                    token => {
+                        return Err(anyhow!(
+                            "expected an operator after '{}', found {}",
+                            word,
+                            token
+                        ));
+                    }
+                };
+                match self.next()? {
+                    Token::Number(value) => Ok(Expr::Compare(field, comparison, value)),
+                    token => Err(anyhow!(
+                        "'{}' is compared with a number, found {}",
+                        word,
+                        token
+                    )),
+                }
+            }
+            token => Err(anyhow!("expected a field or '(', found {}", token)),
+        }
+    }
+
+    // condition = type, condition != type or condition in (type, ...)
+    fn condition(&mut self) -> Result<Expr> {
+        match self.next()? {
+            Token::Operator(operator) if operator == "=" || operator == "==" => {
+                Ok(Expr::Condition(vec![self.weather_type()?]))
+            }
+            Token::Operator(operator) if operator == "!=" => {
+                Ok(Expr::Not(Box::new(Expr::Condition(vec![
+                    self.weather_type()?,
+                ]))))
+            }
+            Token::Word(word) if word == "in" => {
+                match self.next()? {
+                    Token::Open => {}
+                    token => return Err(anyhow!("expected '(' after 'in', found {}", token)),
+                }
+                let mut types = vec![self.weather_type()?];
+                loop {
+                    match self.next()? {
+                        Token::Comma => types.push(self.weather_type()?),
+                        Token::Close => return Ok(Expr::Condition(types)),
+                        token => return Err(anyhow!("expected ',' or ')', found {}", token)),
+                    }
+                }
+            }
+            token => Err(anyhow!(
+                "'condition' is compared with =, != or in, found {}",
+                token
+            )),
+        }
+    }
+
+    fn weather_type(&mut self) -> Result<WeatherType> {
+        match self.next()? {
+            Token::Word(word) => WeatherType::get_weathertype(&word),
+            token => Err(anyhow!("expected a weather type, found {}", token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observation(weather: WeatherType, temperature: f64) -> Observation {
+        Observation {
+            weather: Some(weather),
+            temperature: Some(temperature),
+            wind_speed: Some(4.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let rule =
+            WeatherRule::parse("freezing", "temp < 0 and condition in (snow, drizzle)").unwrap();
+        assert_eq!(
+            rule.expr,
+            Expr::And(
+                Box::new(Expr::Compare(Field::Temp, Comparison::Less, 0.0)),
+                Box::new(Expr::Condition(vec![
+                    WeatherType::Snow,
+                    WeatherType::Drizzle
+                ]))
+            )
+        );
+        // and binds stronger than or
+        let rule = WeatherRule::parse("a", "temp > 1 or temp > 2 and not temp > 3").unwrap();
+        assert!(matches!(rule.expr, Expr::Or(_, _)));
+        let rule = WeatherRule::parse("a", "condition != clear").unwrap();
+        assert_eq!(
+            rule.expr,
+            Expr::Not(Box::new(Expr::Condition(vec![WeatherType::Clear])))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |source: &str| WeatherRule::parse("rule", source).unwrap_err().to_string();
+        assert!(error("tmp > 28").starts_with("unknown field 'tmp'"));
+        assert_eq!(
+            error("temp > rain"),
+            "'temp' is compared with a number, found 'rain'"
+        );
+        assert_eq!(
+            error("condition > 3"),
+            "'condition' is compared with =, != or in, found '>'"
+        );
+        assert_eq!(
+            error("condition in (rainy)"),
+            "not a known weathertype: rainy"
+        );
+        assert_eq!(error("(temp > 1"), "unexpected end of rule");
+        assert_eq!(
+            error("temp > 1 temp"),
+            "unexpected 'temp' after the end of the rule"
+        );
+        assert_eq!(error("temp => 1"), "unknown operator '=>'");
+        assert_eq!(error("temp > 1 & temp < 2"), "unexpected character '&'");
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let rules = vec![
+            WeatherRule::parse("hot", "temp > 28").unwrap(),
+            WeatherRule::parse("windy", "wind_speed >= 10").unwrap(),
+            WeatherRule::parse("freezing", "temp < 0 and condition in (snow, drizzle)").unwrap(),
+            WeatherRule::parse("gusty", "wind_gust > 5").unwrap(),
+        ];
+        assert_eq!(
+            evaluate(&rules, &observation(WeatherType::Clear, 30.0)),
+            Some("hot")
+        );
+        // Heavy snow belongs to snow.
+        assert_eq!(
+            evaluate(&rules, &observation(WeatherType::HeavySnow, -2.5)),
+            Some("freezing")
+        );
+        assert_eq!(
+            evaluate(&rules, &observation(WeatherType::Rain, -2.5)),
+            None
+        );
+        let windy = Observation {
+            wind_speed: Some(10.0),
+            ..observation(WeatherType::Clear, 30.0)
+        };
+        // Rules are checked in order.
+        assert_eq!(evaluate(&rules, &windy), Some("hot"));
+        // Missing wind gusts never match.
+        assert_eq!(evaluate(&rules[3..], &windy), None);
+    }
+}
@@ -0,0 +1,279 @@
+use crate::types::default_types::IMAGE_EXTENSIONS;
+use glob::Pattern;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Rules for discovering image files inside a wallpaper folder.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    pub recursive: bool,
+    // Maximum folder depth below the scanned folder, None is unlimited.
+    pub max_depth: Option<usize>,
+    // Lowercase extensions, matched case-insensitively.
+    pub extensions: Vec<String>,
+    // Globs matched against the path relative to the scanned folder.
+    pub include: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+    pub hidden_files: bool,
+    pub follow_symlinks: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            recursive: false,
+            max_depth: None,
+            extensions: IMAGE_EXTENSIONS.iter().map(|ext| ext.to_string()).collect(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            hidden_files: false,
+            follow_symlinks: true,
+        }
+    }
+}
+
+impl ScanOptions {
+    fn is_image(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| self.extensions.contains(&ext.to_lowercase()))
+    }
+
+    fn is_included(&self, relative: &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches_path(relative)))
+            && !self.is_excluded(relative)
+    }
+
+    fn is_excluded(&self, relative: &Path) -> bool {
+        self.exclude.iter().any(|p| p.matches_path(relative))
+    }
+
+    // Check an image path, relative to the folder being searched, against these options.
+    // Gives the same result as find_images for images found by a full scan.
+    pub fn accepts(&self, relative: &Path) -> bool {
+        let depth = relative.components().count().saturating_sub(1);
+        let max_depth = match self.recursive {
+            true => self.max_depth.unwrap_or(usize::MAX),
+            false => 0,
+        };
+        let is_hidden = relative
+            .components()
+            .any(|component| component.as_os_str().to_string_lossy().starts_with('.'));
+        let in_excluded_folder = relative
+            .ancestors()
+            .skip(1)
+            .any(|folder| !folder.as_os_str().is_empty() && self.is_excluded(folder));
+        depth <= max_depth
+            && (self.hidden_files || !is_hidden)
+            && self.is_image(relative)
+            && !in_excluded_folder
+            && self.is_included(relative)
+    }
+}
+
+// Find all image files in a folder. Missing or unreadable folders yield no images.
+pub fn find_images(folder: &Path, options: &ScanOptions) -> Vec<String> {
+    let mut images = Vec::new();
+    let mut visited = HashSet::new();
+    scan_folder(folder, folder, 0, options, &mut visited, &mut images);
+    images.sort();
+    images
+}
+
+fn scan_folder(
+    root: &Path,
+    folder: &Path,
+    depth: usize,
+    options: &ScanOptions,
+    visited: &mut HashSet<PathBuf>,
+    images: &mut Vec<String>,
+) {
+    // Guard against symlink loops by remembering every real folder that was scanned.
+    if let Ok(real_path) = folder.canonicalize()
+        && !visited.insert(real_path)
+    {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(folder) else {
+        return;
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let is_hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if is_hidden && !options.hidden_files {
+            continue;
+        }
+        let is_symlink = entry.file_type().is_ok_and(|t| t.is_symlink());
+        if is_symlink && !options.follow_symlinks {
+            continue;
+        }
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        if path.is_dir() {
+            let within_depth = options.max_depth.is_none_or(|max| depth < max);
+            if options.recursive && within_depth && !options.is_excluded(relative) {
+                scan_folder(root, &path, depth + 1, options, visited, images);
+            }
+        } else if path.is_file() && options.is_image(&path) && options.is_included(relative) {
+            images.push(path.display().to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    // Fixture tree:
+    // a.jpg, B.JPG, notes.txt, .hidden.png, raw/c.png, raw/deep/d.webp, thumbs/e.jpg
+    fn fixture() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("raw/deep")).unwrap();
+        fs::create_dir_all(root.join("thumbs")).unwrap();
+        for file in [
+            "a.jpg",
+            "B.JPG",
+            "notes.txt",
+            ".hidden.png",
+            "raw/c.png",
+            "raw/deep/d.webp",
+            "thumbs/e.jpg",
+        ] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        dir
+    }
+
+    fn names(root: &Path, images: Vec<String>) -> Vec<String> {
+        images
+            .iter()
+            .map(|image| {
+                Path::new(image)
+                    .strip_prefix(root)
+                    .unwrap()
+                    .display()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_flat_scan() {
+        let dir = fixture();
+        let images = find_images(dir.path(), &ScanOptions::default());
+        assert_eq!(names(dir.path(), images), vec!["B.JPG", "a.jpg"]);
+    }
+
+    #[test]
+    fn test_recursive_scan() {
+        let dir = fixture();
+        let mut options = ScanOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        let images = find_images(dir.path(), &options);
+        assert_eq!(
+            names(dir.path(), images),
+            vec![
+                "B.JPG",
+                "a.jpg",
+                "raw/c.png",
+                "raw/deep/d.webp",
+                "thumbs/e.jpg"
+            ]
+        );
+
+        options.max_depth = Some(1);
+        let images = find_images(dir.path(), &options);
+        assert_eq!(
+            names(dir.path(), images),
+            vec!["B.JPG", "a.jpg", "raw/c.png", "thumbs/e.jpg"]
+        );
+    }
+
+    #[test]
+    fn test_globs_and_policies() {
+        let dir = fixture();
+        let options = ScanOptions {
+            recursive: true,
+            extensions: vec!["jpg".to_string(), "png".to_string()],
+            include: vec![
+                Pattern::new("*.jpg").unwrap(),
+                Pattern::new("raw/*").unwrap(),
+            ],
+            exclude: vec![Pattern::new("thumbs").unwrap()],
+            hidden_files: true,
+            ..Default::default()
+        };
+        let images = find_images(dir.path(), &options);
+        assert_eq!(names(dir.path(), images), vec!["a.jpg", "raw/c.png"]);
+
+        let options = ScanOptions {
+            hidden_files: true,
+            ..Default::default()
+        };
+        let images = find_images(dir.path(), &options);
+        assert_eq!(
+            names(dir.path(), images),
+            vec![".hidden.png", "B.JPG", "a.jpg"]
+        );
+    }
+
+    #[test]
+    fn test_accepts() {
+        let options = ScanOptions {
+            recursive: true,
+            max_depth: Some(1),
+            include: vec![
+                Pattern::new("*.jpg").unwrap(),
+                Pattern::new("raw/*").unwrap(),
+            ],
+            exclude: vec![Pattern::new("thumbs").unwrap()],
+            ..Default::default()
+        };
+        assert!(options.accepts(Path::new("a.jpg")));
+        assert!(options.accepts(Path::new("raw/c.png")));
+        assert!(!options.accepts(Path::new("raw/deep/d.jpg")));
+        assert!(!options.accepts(Path::new("thumbs/e.jpg")));
+        assert!(!options.accepts(Path::new(".hidden.jpg")));
+        assert!(!options.accepts(Path::new("notes.txt")));
+        assert!(!ScanOptions::default().accepts(Path::new("raw/c.png")));
+    }
+
+    #[test]
+    fn test_symlink_policy() {
+        let dir = fixture();
+        let root = dir.path();
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("f.jpg"), b"").unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("linked")).unwrap();
+        // A link back to the root must not loop.
+        std::os::unix::fs::symlink(root, root.join("raw/loop")).unwrap();
+
+        let mut options = ScanOptions {
+            recursive: true,
+            exclude: vec![Pattern::new("thumbs").unwrap()],
+            ..Default::default()
+        };
+        let images = find_images(root, &options);
+        assert_eq!(
+            names(root, images),
+            vec![
+                "B.JPG",
+                "a.jpg",
+                "linked/f.jpg",
+                "raw/c.png",
+                "raw/deep/d.webp"
+            ]
+        );
+
+        options.follow_symlinks = false;
+        let images = find_images(root, &options);
+        assert_eq!(
+            names(root, images),
+            vec!["B.JPG", "a.jpg", "raw/c.png", "raw/deep/d.webp"]
+        );
+    }
+}
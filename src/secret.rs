@@ -0,0 +1,176 @@
+use crate::fmt;
+use anyhow::{Context, Result, anyhow};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use zbus::Message;
+use zbus::blocking::Connection;
+use zbus::zvariant::{DynamicType, OwnedObjectPath, OwnedValue, Value};
+
+// Environment variable that overrides the key sources in config.ini.
+pub const KEY_ENV: &str = "FEHTHER_API_KEY";
+
+// API key or access token. Debug and Display never show the key itself, use expose()
+// where the key has to be sent.
+#[derive(Clone, Default, PartialEq)]
+pub struct ApiKey(String);
+
+impl ApiKey {
+    pub fn new(key: &str) -> ApiKey {
+        ApiKey(key.trim().to_string())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    // Replace the key in text that is about to be logged.
+    pub fn redact(&self, text: &str) -> String {
+        match self.0.is_empty() {
+            true => text.to_string(),
+            false => text.replace(&self.0, "[redacted]"),
+        }
+    }
+}
+
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ApiKey([redacted])")
+    }
+}
+
+impl fmt::Display for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[redacted]")
+    }
+}
+
+// Read the key from a file, e.g. one only readable by the user.
+pub fn read_file(path: &Path) -> Result<ApiKey> {
+    let key = fs::read_to_string(path)
+        .with_context(|| format!("Could not read key-file: {}", path.display()))?;
+    Ok(ApiKey::new(&key))
+}
+
+// Parse "name=value" pairs, separated by whitespace.
+pub fn parse_attributes(attributes: &str) -> Result<HashMap<String, String>> {
+    attributes
+        .split_whitespace()
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+            _ => Err(anyhow!("Invalid key-keyring attribute: {}", pair)),
+        })
+        .collect()
+}
+
+const SECRETS: &str = "org.freedesktop.secrets";
+const SECRETS_PATH: &str = "/org/freedesktop/secrets";
+const SECRETS_SERVICE: &str = "org.freedesktop.Secret.Service";
+
+// Secret as sent by the Secret Service: session, parameters, value and content type.
+type Secret = (OwnedObjectPath, Vec<u8>, Vec<u8>, String);
+
+fn call<B>(
+    connection: &Connection,
+    path: &str,
+    interface: &str,
+    method: &str,
+    body: &B,
+) -> Result<Message>
+where
+    B: serde::Serialize + DynamicType,
+{
+    Ok(connection.call_method(Some(SECRETS), path, Some(interface), method, body)?)
+}
+
+// Look up the key in the Secret Service keyring (GNOME Keyring, KeePassXC, ...) over
+// the session bus, matching an item by its attributes.
+pub fn keyring(attributes: &HashMap<String, String>) -> Result<ApiKey> {
+    let connection = Connection::session().context("Could not connect to the session bus")?;
+    let (unlocked, locked): (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) = call(
+        &connection,
+        SECRETS_PATH,
+        SECRETS_SERVICE,
+        "SearchItems",
+        &(attributes,),
+    )
+    .context("Could not search the keyring")?
+    .body()
+    .deserialize()?;
+    let Some(item) = unlocked.first() else {
+        return Err(match locked.is_empty() {
+            true => anyhow!("no keyring item matches the key-keyring attributes"),
+            false => anyhow!("the keyring item holding the key is locked"),
+        });
+    };
+    // The plain algorithm sends the secret unencrypted, fine on the local session bus.
+    let (_, session): (OwnedValue, OwnedObjectPath) = call(
+        &connection,
+        SECRETS_PATH,
+        SECRETS_SERVICE,
+        "OpenSession",
+        &("plain", Value::from("")),
+    )?
+    .body()
+    .deserialize()?;
+    let secrets = call(
+        &connection,
+        SECRETS_PATH,
+        SECRETS_SERVICE,
+        "GetSecrets",
+        &(vec![item], &session),
+    )
+    .context("Could not read the keyring item")
+    .and_then(|reply| {
+        Ok(reply
+            .body()
+            .deserialize::<HashMap<OwnedObjectPath, Secret>>()?)
+    });
+    call(
+        &connection,
+        &session,
+        "org.freedesktop.Secret.Session",
+        "Close",
+        &(),
+    )
+    .ok();
+    let (_, _, value, _) = secrets?
+        .remove(item)
+        .ok_or_else(|| anyhow!("the keyring did not return the key"))?;
+    Ok(ApiKey::new(&String::from_utf8(value)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redacted() {
+        let key = ApiKey::new("0123456789abcdef\n");
+        assert_eq!(key.expose(), "0123456789abcdef");
+        assert_eq!(format!("{:?}", key), "ApiKey([redacted])");
+        assert_eq!(format!("{}", key), "[redacted]");
+        assert_eq!(
+            key.redact("GET /weather?appid=0123456789abcdef failed"),
+            "GET /weather?appid=[redacted] failed"
+        );
+        assert_eq!(ApiKey::default().redact("text"), "text");
+    }
+
+    #[test]
+    fn test_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("owm");
+        fs::write(&path, "from-file\n").unwrap();
+        assert_eq!(read_file(&path).unwrap().expose(), "from-file");
+        assert!(read_file(&dir.path().join("missing")).is_err());
+        assert_eq!(
+            parse_attributes("service=openweathermap user=me").unwrap(),
+            HashMap::from([
+                ("service".to_string(), "openweathermap".to_string()),
+                ("user".to_string(), "me".to_string()),
+            ])
+        );
+        assert!(parse_attributes("service").is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use crate::error::FehtherError;
+use crate::transition::Transition;
+use crate::types::backend::Backend;
+use anyhow::{Context, Result, anyhow};
+use std::process::Command;
+
+// Hand images to the configured backend. feh sets one image per output in order, swww sets
+// the first image on all outputs and runs its own fade transition when crossfade is on.
+pub fn set_images(
+    backend: Backend,
+    feh_mode: &str,
+    images: &[String],
+    transition: &Transition,
+) -> Result<()> {
+    let mut command = match backend {
+        Backend::Feh => {
+            let mut command = Command::new("feh");
+            command.arg(feh_mode).args(images);
+            command
+        }
+        Backend::Swww => {
+            let image = images.first().ok_or_else(|| anyhow!("no image to set"))?;
+            let mut command = Command::new("swww");
+            command.args(["img", image, "--resize", swww_resize(feh_mode)]);
+            if transition.crossfade {
+                command.args([
+                    "--transition-type",
+                    "fade",
+                    "--transition-duration",
+                    &transition.duration.to_string(),
+                ]);
+            } else {
+                command.args(["--transition-type", "none"]);
+            }
+            command
+        }
+    };
+    let output = command
+        .output()
+        .with_context(|| FehtherError::Setter(backend))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed: {}",
+            backend,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .context(FehtherError::Setter(backend)));
+    }
+    Ok(())
+}
+
+// Translate the feh mode into the closest swww resize option.
+fn swww_resize(feh_mode: &str) -> &'static str {
+    match feh_mode {
+        "--bg-fill" => "crop",
+        "--bg-center" | "--bg-tile" => "no",
+        _ => "fit",
+    }
+}
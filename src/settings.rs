@@ -1,35 +1,123 @@
-use crate::Command;
 use crate::PathBuf;
-use crate::WeatherResponse;
 use crate::WeatherType;
-use crate::fs;
+use crate::blend::{self, BlendSettings};
+use crate::debounce::Debounce;
+use crate::effects::{self, EffectSettings};
+use crate::error::{self, FehtherError};
+use crate::fetch_weather::Observation;
+use crate::filter::{ImageFilter, InfoCache};
+use crate::forecast;
+use crate::home_assistant::HomeAssistant;
+use crate::hooks::{self, Event, HookSettings};
+use crate::http::HttpClient;
+use crate::library::{Library, Record};
+use crate::location::Location;
+use crate::location_source::{self, LocationFollower};
+use crate::overlay::{self, OverlaySettings};
+use crate::palette::{self, PaletteSettings};
+use crate::rules::{self, WeatherRule};
+use crate::scan::{self, ScanOptions};
+use crate::secret::ApiKey;
+use crate::setter;
+use crate::tags::{self, TagIndex};
+use crate::transition::{self, Transition};
+use crate::types::backend::Backend;
 use crate::types::daytime::Daytime;
-use crate::types::default_types::IMAGE_EXTENSIONS;
+use crate::types::default_types::{FORECAST_INTERVAL, HISTORY_LENGTH, QUOTA_BACKOFF};
+use crate::types::fallback::FallbackLevel;
+use crate::types::forecastpolicy::ForecastPolicy;
 use crate::types::modes::Mode;
 use crate::utils;
+use crate::weather_source::WeatherSource;
 use std::collections::HashMap;
+use std::path::Path;
 
 // All possible settings, parsed from config.ini.
 #[derive(Debug)]
 pub struct Settings {
     pub current_loop: bool,
-    pub key: String,
-    pub city: String,
-    pub country: String,
+    // Never printed, Debug shows it redacted.
+    pub key: ApiKey,
+    pub http: HttpClient,
+    pub weather_source: WeatherSource,
+    // Home Assistant instance for the weather source and control entities.
+    pub home_assistant: Option<HomeAssistant>,
+    // Set over D-Bus or by the Home Assistant pause entity, the wallpaper is left alone
+    // while paused.
+    pub paused: bool,
+    // Export the org.fehther.Daemon service on the session bus.
+    pub dbus: bool,
+    pub location: Location,
+    // Service used to resolve an IP location.
+    pub geolocation_url: String,
+    // Optional source of the current position, replaces the location when it moves.
+    pub follow: Option<LocationFollower>,
+    // IANA timezone of followed coordinates, used for the overlay clock of sources that
+    // don't report it.
+    pub timezone: Option<String>,
     pub path: String,
     pub modes: Vec<Mode>,
     pub daytime: Daytime,
     pub golden_hour: bool,
     pub disabled_daytimes: Option<Vec<Daytime>>,
     pub folder_names: HashMap<Daytime, String>,
+    pub default_folder: Option<String>,
+    pub tag_mode: bool,
+    pub index_file: PathBuf,
+    pub library_file: PathBuf,
+    pub library_watch: bool,
+    pub rescan_interval: i32,
+    pub rescan_timer: i32,
+    pub scan: ScanOptions,
+    pub filter: ImageFilter,
     pub custom_weather_groups: bool,
     pub weather_groups: HashMap<String, Vec<WeatherType>>,
+    pub weather_rules: Vec<WeatherRule>,
+    // Name of the first matching weather rule, used as folder ahead of the weather group.
+    pub weather_rule: Option<String>,
+    // Holds back weather and rule changes until they are stable.
+    pub debounce: Debounce<(WeatherType, Option<String>)>,
+    // Hours of forecast combined with the current weather, 0 uses current weather only.
+    pub forecast_horizon: u32,
+    pub forecast_policy: ForecastPolicy,
+    pub forecast: Vec<(i64, WeatherType)>,
+    pub forecast_timer: i32,
     pub sunset_timer: i32,
     pub interval: i32,
     pub weather: WeatherType,
     pub feh_mode: String,
+    pub backend: Backend,
+    pub transition: Transition,
     pub timer: i32,
     pub recovery_mode: bool,
+    // Polls left without weather requests after the provider's rate limit was reached.
+    pub weather_backoff: i32,
+    // Set once the provider rejected the API key, the weather is not requested again.
+    pub weather_stopped: bool,
+    pub current_images: Vec<String>,
+    // Images picked from the library for the current wallpaper, before effects and overlay.
+    // In blend mode the day and night image of the scene.
+    pub shown: Vec<String>,
+    // Earlier picks, most recent last.
+    pub history: Vec<Vec<String>>,
+    // Folder used ahead of all others until cleared, set over D-Bus.
+    pub override_folder: Option<String>,
+    pub blend: BlendSettings,
+    pub blend_scene: Option<(String, String)>,
+    pub blend_bucket: Option<u32>,
+    pub blend_timer: i32,
+    pub coordinates: Option<(f64, f64)>,
+    pub sun_times: Option<(i32, i32)>,
+    pub effects: EffectSettings,
+    pub precipitation: f64,
+    pub overlay: OverlaySettings,
+    pub hooks: HookSettings,
+    pub palette: PaletteSettings,
+    pub observation: Option<Observation>,
+    // Images and output sizes the overlay was last drawn onto, and the text drawn.
+    pub overlay_base: Vec<String>,
+    pub overlay_outputs: Vec<(u32, u32)>,
+    pub overlay_text: String,
 }
 
 // Full configuration is stored in the Settings struct and called through traits.
@@ -41,35 +129,248 @@ impl Settings {
             .is_some_and(|daytimes| daytimes.contains(&self.daytime))
     }
 
-    // Fetch path to wallpaper directory.
-    pub fn fetch_path(&self) -> String {
-        // If in recovery mode, return only the base path
-        if self.recovery_mode {
-            return self.path.clone();
+    // Fetch all candidate wallpaper directories, most specific first. The first directory
+    // containing images is used, the rest serve as fallbacks.
+    pub fn fetch_fallback_chain(&self) -> Vec<(FallbackLevel, PathBuf)> {
+        let root = PathBuf::from(&self.path);
+        let mut chain = Vec::new();
+        if let Some(folder) = &self.override_folder {
+            chain.push((FallbackLevel::Override, root.join(folder)));
         }
 
-        let is_weather_mode_on = self.modes.contains(&Mode::Weather);
-        let is_daytime_mode_on = self.modes.contains(&Mode::Daytime);
-        let is_daytime_disabled_for_weather = self.is_current_daytime_disabled_for_weather_mode();
+        // If in recovery mode, only the default folder and base path are used.
+        if !self.recovery_mode {
+            let is_weather_mode_on = self.modes.contains(&Mode::Weather);
+            let is_daytime_mode_on = self.modes.contains(&Mode::Daytime);
+            let is_daytime_disabled_for_weather =
+                self.is_current_daytime_disabled_for_weather_mode();
 
-        let mut final_path = self.path.clone();
+            let daytime_path = self.fetch_daytime_path();
 
-        // Append daytime folder if daytime mode is on
-        if is_daytime_mode_on {
-            final_path.push_str(&self.fetch_folder_name());
-        }
+            // Append weather group if weather mode is on AND
+            // (daytime mode is off OR current daytime is NOT disabled for weather)
+            if is_weather_mode_on && !(is_daytime_mode_on && is_daytime_disabled_for_weather) {
+                if let Some(rule) = &self.weather_rule {
+                    chain.push((FallbackLevel::Rule, daytime_path.join(rule)));
+                }
+                chain.extend(self.fetch_weather_chain(&daytime_path, &self.weather));
+            }
 
-        // Append weather group if weather mode is on AND
-        // (daytime mode is off OR current daytime is NOT disabled for weather)
-        if is_weather_mode_on && !(is_daytime_mode_on && is_daytime_disabled_for_weather) {
-            // Add a separator if a previous segment (daytime folder) was added
             if is_daytime_mode_on {
-                final_path.push('/');
+                chain.push((FallbackLevel::Daytime, daytime_path));
             }
-            final_path.push_str(&self.check_group());
         }
 
-        final_path
+        if let Some(default_folder) = &self.default_folder {
+            chain.push((FallbackLevel::Default, root.join(default_folder)));
+        }
+        chain.push((FallbackLevel::Root, root));
+        chain
+    }
+
+    // Daytime folder if daytime mode is on, otherwise the wallpaper path.
+    fn fetch_daytime_path(&self) -> PathBuf {
+        let root = PathBuf::from(&self.path);
+        if self.modes.contains(&Mode::Daytime) {
+            root.join(self.fetch_folder_name())
+        } else {
+            root
+        }
+    }
+
+    // Weather folders of the fallback chain for a weather type.
+    fn fetch_weather_chain(
+        &self,
+        daytime_path: &Path,
+        weather: &WeatherType,
+    ) -> Vec<(FallbackLevel, PathBuf)> {
+        let mut chain = Vec::new();
+        let group = self.check_group(weather);
+        chain.push((FallbackLevel::Weather, daytime_path.join(&group)));
+        // Nested groups (e.g. rain/heavy) fall back to their parent folders first.
+        let mut parent = Path::new(&group).parent();
+        while let Some(folder) = parent.filter(|p| !p.as_os_str().is_empty()) {
+            chain.push((FallbackLevel::WeatherParent, daytime_path.join(folder)));
+            parent = folder.parent();
+        }
+        // Intensities (e.g. heavy rain) fall back to the folder of their base type.
+        let family = weather.family();
+        let family_path = daytime_path.join(self.check_group(&family));
+        if family != *weather && !chain.iter().any(|(_, path)| *path == family_path) {
+            chain.push((FallbackLevel::WeatherFamily, family_path));
+        }
+        chain
+    }
+
+    // First existing weather folder for a weather type, or its group folder if none exists.
+    fn fetch_weather_folder(&self, weather: &WeatherType) -> PathBuf {
+        let chain = self.fetch_weather_chain(&self.fetch_daytime_path(), weather);
+        let group = chain[0].1.clone();
+        chain
+            .into_iter()
+            .map(|(_, path)| path)
+            .find(|path| path.is_dir())
+            .unwrap_or(group)
+    }
+
+    // Open the library, a library that cannot be opened is logged and folders are scanned
+    // instead.
+    pub fn open_library(&self) -> Option<Library> {
+        Library::open(&self.library_file)
+            .map_err(|error| {
+                eprintln!(
+                    "fehther: library unavailable, scanning folders: {:#}",
+                    error
+                )
+            })
+            .ok()
+    }
+
+    // Walk the fallback chain and return the first folder containing images.
+    pub fn fetch_path(
+        &self,
+        library: Option<&Library>,
+    ) -> Option<(FallbackLevel, PathBuf, Vec<Record>)> {
+        let mut source = ImageSource::new(self, library);
+        let found = self
+            .fetch_fallback_chain()
+            .into_iter()
+            .find_map(|(level, path)| {
+                let images = source.fetch_images(self, &path);
+                (!images.is_empty()).then_some((level, path, images))
+            });
+        source.save();
+        found
+    }
+
+    // If tag mode is on, load the tag index and bring it up to date with the wallpapers.
+    pub fn fetch_tag_index(&self) -> Option<TagIndex> {
+        self.tag_mode.then(|| {
+            let mut index = TagIndex::load(&self.index_file);
+            if index.refresh(Path::new(&self.path), &self.scan) != (0, 0) {
+                index.save(&self.index_file).ok();
+            }
+            index
+        })
+    }
+
+    // If image filtering is on, load the cache of image headers.
+    fn fetch_info_cache(&self) -> Option<InfoCache> {
+        self.filter
+            .is_active()
+            .then(|| InfoCache::load(&InfoCache::cache_file()))
+    }
+
+    // Fetch images for a folder of the fallback chain from the library. In tag mode the folder
+    // is not read, instead its path segments (e.g. day/rain) are the tags an image must carry.
+    // Images failing the configured filter rules are dropped.
+    fn fetch_images(&self, library: &Library, folder: &Path) -> Vec<Record> {
+        let images = if self.tag_mode {
+            let tags = tags::folder_tags(Path::new(&self.path), folder);
+            library
+                .images()
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|record| record.has_tags(&tags))
+                .collect()
+        } else {
+            library
+                .images_under(folder)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|record| {
+                    Path::new(&record.path)
+                        .strip_prefix(folder)
+                        .is_ok_and(|relative| self.scan.accepts(relative))
+                })
+                .collect()
+        };
+        if self.filter.is_active() {
+            self.filter.apply(images, library)
+        } else {
+            images
+        }
+    }
+
+    // Fetch images for a folder of the fallback chain without the library, from the tag index
+    // in tag mode or by reading the folder.
+    fn fetch_scanned_images(
+        &self,
+        folder: &Path,
+        index: Option<&TagIndex>,
+        cache: Option<&mut InfoCache>,
+    ) -> Vec<Record> {
+        let images = match index {
+            Some(index) => index.matching(&tags::folder_tags(Path::new(&self.path), folder)),
+            None => scan::find_images(folder, &self.scan),
+        };
+        match cache {
+            Some(cache) => self.filter.apply_cached(images, cache),
+            None => images.into_iter().map(Record::new).collect(),
+        }
+    }
+
+    // Describe the fallback chain for the current state, used by `fehther explain`.
+    pub fn explain(&self, library: Option<&Library>) -> String {
+        let mut report = format!("daytime: {}\nweather: {}\n", self.daytime, self.weather);
+        if !self.weather_rules.is_empty() {
+            report.push_str(&format!(
+                "weather rule: {}\n",
+                self.weather_rule.as_deref().unwrap_or("none")
+            ));
+        }
+        report.push_str(&format!(
+            "recovery mode: {}\n\nfallback chain:\n",
+            self.recovery_mode
+        ));
+        let mut source = ImageSource::new(self, library);
+        let mut selected = false;
+        for (level, path) in self.fetch_fallback_chain() {
+            let count = source.fetch_images(self, &path).len();
+            let status = match (self.tag_mode || path.is_dir(), count) {
+                (false, _) => "missing".to_string(),
+                (true, 0) => "no images".to_string(),
+                (true, count) => format!("{} images", count),
+            };
+            // Mark the first folder with images, this is the one set_wallpaper uses.
+            let marker = if !selected && count > 0 {
+                selected = true;
+                "->"
+            } else {
+                "  "
+            };
+            let location = match self.tag_mode {
+                true => format!(
+                    "tags: [{}]",
+                    tags::folder_tags(Path::new(&self.path), &path).join(", ")
+                ),
+                false => path.display().to_string(),
+            };
+            report.push_str(&format!(
+                "{} {:<15} {} ({})\n",
+                marker,
+                level.to_string(),
+                location,
+                status
+            ));
+        }
+        source.save();
+        if !selected {
+            report.push_str("\nno folder in the chain contains images\n");
+        }
+        report
+    }
+
+    // Weather in use. Weather mode keeps settings.weather up to date, with forecast and
+    // stability rules applied, otherwise it is the weather last reported by the source.
+    pub fn current_weather(&self) -> &WeatherType {
+        if self.modes.contains(&Mode::Weather) {
+            return &self.weather;
+        }
+        self.observation
+            .as_ref()
+            .and_then(|observation| observation.weather.as_ref())
+            .unwrap_or(&self.weather)
     }
 
     // Fetch correct folder name.
@@ -77,15 +378,21 @@ impl Settings {
         self.folder_names.get(&self.daytime).unwrap().to_string()
     }
 
-    // Check if current weather type is in a custom weather group.
-    fn check_group(&self) -> String {
+    // Fetch the folder for a weather type, the custom weather group containing it or its
+    // base type, or the name of the weather type itself.
+    fn check_group(&self, weather: &WeatherType) -> String {
         if self.custom_weather_groups {
-            self.weather_groups
-                .iter()
-                .find(|(_, weather_list)| weather_list.contains(&self.weather))
-                .map_or_else(|| self.weather.to_string(), |(group, _)| group.to_string())
+            let find = |weather: &WeatherType| {
+                self.weather_groups
+                    .iter()
+                    .find(|(_, weather_list)| weather_list.contains(weather))
+                    .map(|(group, _)| group.to_string())
+            };
+            find(weather)
+                .or_else(|| find(&weather.family()))
+                .unwrap_or_else(|| weather.to_string())
         } else {
-            self.weather.to_string()
+            weather.to_string()
         }
     }
 
@@ -100,50 +407,912 @@ impl Settings {
     }
 
     // If weather mode is on, check if weather has changed.
-    pub fn check_weather_mode(&mut self, response: &WeatherResponse) {
-        let weather = WeatherType::condition(response.weather[0].id);
-        if self.weather != weather {
+    pub fn check_weather_mode(&mut self, observation: &Observation) {
+        self.precipitation = observation.rain + observation.snow;
+        // Keep the current weather when the source reported no condition.
+        let mut weather = observation
+            .weather
+            .clone()
+            .unwrap_or_else(|| self.weather.clone());
+        if self.forecast_horizon > 0 {
+            weather = forecast::aggregate(
+                &weather,
+                &self.forecast,
+                utils::now_secs() as i64,
+                self.forecast_horizon,
+                self.forecast_policy,
+            );
+        }
+        let rule = rules::evaluate(&self.weather_rules, observation).map(str::to_string);
+        let current = (self.weather.clone(), self.weather_rule.clone());
+        if let Some((weather, rule)) =
+            self.debounce
+                .update(&current, (weather, rule), utils::now_secs())
+        {
+            // Changes that keep the same folder update the weather without a new wallpaper.
+            // Without ignore-same-group this only applies to intensities of one type, e.g. light
+            // rain and rain.
+            let is_same_folder = rule == self.weather_rule
+                && (self.debounce.settings.ignore_same_group
+                    || weather.family() == self.weather.family())
+                && self.fetch_weather_folder(&weather) == self.fetch_weather_folder(&self.weather);
+            if !is_same_folder {
+                self.current_loop = true;
+            }
             self.weather = weather;
-            self.current_loop = true;
+            self.weather_rule = rule;
+            self.run_hook(Event::Weather, Some(observation));
+        }
+    }
+
+    // Check if the weather provider may be asked on this poll.
+    pub fn check_weather_backoff(&mut self) -> bool {
+        if self.weather_stopped {
+            return false;
+        }
+        if self.weather_backoff > 0 {
+            self.weather_backoff -= 1;
+            return false;
+        }
+        true
+    }
+
+    // React to a failed weather request. A rejected key stops weather requests, the rate
+    // limit pauses them, anything else is retried on the next poll.
+    pub fn weather_failed(&mut self, what: &str, error: &anyhow::Error) {
+        match error::classify(error) {
+            Some(FehtherError::ProviderAuth(_)) => {
+                eprintln!(
+                    "fehther: {} failed, no more weather requests: {}",
+                    what,
+                    error::describe(error, &self.key)
+                );
+                self.weather_stopped = true;
+            }
+            Some(FehtherError::ProviderQuota(_)) => {
+                eprintln!(
+                    "fehther: {} failed, pausing for {} minutes: {}",
+                    what,
+                    QUOTA_BACKOFF,
+                    error::describe(error, &self.key)
+                );
+                self.weather_backoff = QUOTA_BACKOFF;
+            }
+            // Network and request errors are only reported once, they tend to last a while.
+            Some(FehtherError::Network(_) | FehtherError::ProviderRequest(_))
+                if self.recovery_mode => {}
+            _ => eprintln!(
+                "fehther: {} failed: {}",
+                what,
+                error::describe(error, &self.key)
+            ),
+        }
+    }
+
+    // If forecast mode is on, check if the forecast is due for a refresh. Forecasts only
+    // change every few hours, so they are fetched every FORECAST_INTERVAL minutes.
+    pub fn check_forecast_timer(&mut self) -> bool {
+        if self.forecast_horizon == 0 || !self.modes.contains(&Mode::Weather) {
+            return false;
+        }
+        self.forecast_timer -= 1;
+        if self.forecast_timer > 0 {
+            return false;
+        }
+        self.forecast_timer = FORECAST_INTERVAL;
+        true
+    }
+
+    // If location following is on, switch to the current position once it has moved far
+    // enough from the configured or last followed location.
+    pub fn check_location(&mut self) {
+        let Some(follow) = self.follow.as_mut() else {
+            return;
+        };
+        if !follow.is_due() {
+            return;
+        }
+        let (lat, lon) = match follow.source.position() {
+            Ok(Some(position)) => position,
+            Ok(None) => return,
+            Err(error) => {
+                eprintln!("fehther: location source failed: {:#}", error);
+                return;
+            }
+        };
+        if let Location::Coordinates {
+            lat: current_lat,
+            lon: current_lon,
+        } = self.location
+            && location_source::haversine((current_lat, current_lon), (lat, lon))
+                < follow.min_distance
+        {
+            return;
+        }
+        match crate::location::coordinates(lat, lon) {
+            Ok(location) => {
+                let timezone = utils::fetch_timezone(lat, lon);
+                println!("fehther: moved to {} ({})", location, timezone);
+                self.location = location;
+                self.timezone = Some(timezone);
+                // Sunrise, sunset and weather are fetched for the new place right away.
+                self.forecast_timer = 0;
+                self.current_loop = true;
+            }
+            Err(error) => eprintln!("fehther: location source failed: {:#}", error),
         }
     }
 
     // If daytime mode is on, check if daytime has changed
-    pub fn check_daytime_mode(&mut self, response: &WeatherResponse) {
+    pub fn check_daytime_mode(&mut self, observation: &Observation) {
+        // Without sunrise and sunset the daytime stays as it is.
+        let (Some(sunrise), Some(sunset)) = (observation.sunrise, observation.sunset) else {
+            return;
+        };
         let daytime = utils::fetch_daytime(
-            response.sys.sunrise,
-            response.sys.sunset,
+            sunrise as i32,
+            sunset as i32,
             self.golden_hour,
             self.sunset_timer,
         );
         if self.daytime != daytime {
             self.daytime = daytime;
             self.current_loop = true;
+            self.run_hook(Event::Daytime, Some(observation));
+        }
+    }
+
+    // If weather effects are on without weather mode, redraw the images on screen when the
+    // effect changes. While paused the change is picked up after resuming.
+    pub fn check_effects(&mut self, observation: &Observation) {
+        let weather = observation
+            .weather
+            .clone()
+            .unwrap_or_else(|| self.weather.clone());
+        let precipitation = observation.rain + observation.snow;
+        if self.paused
+            || (weather == self.weather
+                && effects::intensity(&weather, precipitation)
+                    == effects::intensity(&self.weather, self.precipitation))
+        {
+            return;
+        }
+        self.weather = weather;
+        self.precipitation = precipitation;
+        if self.modes.contains(&Mode::Blend) {
+            self.set_blended_wallpaper(false)
+                .unwrap_or_else(|error| eprintln!("fehther: weather effect failed: {:#}", error));
+        } else if !self.shown.is_empty() {
+            self.redraw();
+        }
+    }
+
+    // If library rescans are on, check if timer has reached limit.
+    pub fn check_library_rescan(&mut self) {
+        if self.rescan_interval == 0 {
+            return;
+        }
+        self.rescan_timer += 1;
+        if self.rescan_timer >= self.rescan_interval {
+            self.rescan_timer = 0;
+            match Library::open(&self.library_file)
+                .and_then(|library| library.rescan(Path::new(&self.path), &self.scan))
+            {
+                Ok((0, 0)) => {}
+                Ok((updated, removed)) => {
+                    println!("fehther: library rescan, {updated} updated, {removed} removed")
+                }
+                Err(error) => eprintln!("fehther: library rescan failed: {:#}", error),
+            }
+        }
+    }
+
+    // If blend mode is on, re-render the current scene when the blend weight has changed.
+    pub fn check_blend_mode(&mut self, observation: &Observation) {
+        self.coordinates = observation.coordinates;
+        if let (Some(sunrise), Some(sunset)) = (observation.sunrise, observation.sunset) {
+            self.sun_times = Some((sunrise as i32, sunset as i32));
+        }
+        self.blend_timer += 1;
+        if self.blend_timer < self.blend.interval {
+            return;
+        }
+        self.blend_timer = 0;
+        // A pending wallpaper change renders the new weight anyway.
+        if !self.current_loop
+            && self
+                .blend_bucket
+                .is_some_and(|bucket| bucket != self.fetch_blend_bucket())
+        {
+            self.set_blended_wallpaper(false)
+                .unwrap_or_else(|error| eprintln!("fehther: blending failed: {:#}", error));
+        }
+    }
+
+    // Current blend weight bucket, from solar elevation or sunrise and sunset.
+    fn fetch_blend_bucket(&self) -> u32 {
+        let (sunrise, sunset) = self.sun_times.unwrap_or_default();
+        let weight = utils::fetch_day_weight(
+            utils::now_secs() as i64,
+            sunrise as i64,
+            sunset as i64,
+            self.coordinates,
+            self.blend.twilight,
+            self.sunset_timer,
+        );
+        self.blend.bucket(weight)
+    }
+
+    // Set a blend of a paired day and night image. A new scene is only picked when the
+    // wallpaper changes, weight updates re-render the current scene.
+    fn set_blended_wallpaper(&mut self, new_scene: bool) -> Result<(), anyhow::Error> {
+        if new_scene || self.blend_scene.is_none() {
+            let library = self.open_library();
+            let root = Path::new(&self.path);
+            let day_folder = root.join(&self.folder_names[&Daytime::Day]);
+            let night_folder = root.join(&self.folder_names[&Daytime::Night]);
+            let images = |folder: &Path| match &library {
+                Some(library) => library.images_under(folder),
+                None => Ok(scan::find_images(folder, &self.scan)
+                    .into_iter()
+                    .map(Record::new)
+                    .collect()),
+            };
+            let pairs = blend::find_pairs(
+                (&day_folder, images(&day_folder)?),
+                (&night_folder, images(&night_folder)?),
+                &self.scan,
+            );
+            self.blend_scene = blend::select_pair(pairs);
+            if let (Some(library), Some((day, _))) = (&library, &self.blend_scene) {
+                library.mark_shown(day, utils::now_secs())?;
+            }
+        }
+        let Some((day, night)) = self.blend_scene.clone() else {
+            eprintln!("fehther: no paired day and night images found");
+            return Ok(());
+        };
+
+        let bucket = self.fetch_blend_bucket();
+        let size = utils::fetch_outputs().first().copied();
+        let image = blend::render_cached(
+            (&day, &night),
+            bucket,
+            &self.blend,
+            size,
+            &utils::fetch_cache_dir().join("blend"),
+        )?;
+        println!(
+            "fehther: blending {} at {}/{}",
+            day, bucket, self.blend.buckets
+        );
+        let outputs: Vec<(u32, u32)> = size.into_iter().collect();
+        let blended = image.clone();
+        let images = self.render_effects(vec![image], &outputs);
+        let images = self.render_overlay(images, &outputs);
+        setter::set_images(self.backend, &self.feh_mode, &images, &self.transition)?;
+        self.blend_bucket = Some(bucket);
+        self.current_images = images;
+        self.prune_renders();
+        // Weight updates only re-render the same scene.
+        if new_scene {
+            self.push_history();
+            self.shown = vec![day, night];
+            self.update_palette(&blended);
+            self.run_hook(Event::Wallpaper, None);
+        }
+        Ok(())
+    }
+
+    // If the palette is on, write the palette of a new wallpaper before hooks are run,
+    // so they can use it.
+    fn update_palette(&self, image: &str) {
+        if !self.palette.enabled {
+            return;
+        }
+        if let Err(error) = palette::update(Path::new(image), &self.palette) {
+            eprintln!("fehther: palette failed: {:#}", error);
+        }
+    }
+
+    // Run the hook for an event in the background, if one is set. It gets the images on
+    // screen, daytime, weather and temperature, from the latest observation unless one is
+    // being checked.
+    fn run_hook(&self, event: Event, observation: Option<&Observation>) {
+        let Some(command) = self.hooks.command(event) else {
+            return;
+        };
+        let observation = observation.or(self.observation.as_ref());
+        let temperature = observation.and_then(|observation| observation.temperature);
+        let weather = match observation.and_then(|observation| observation.weather.as_ref()) {
+            Some(weather) if !self.modes.contains(&Mode::Weather) => weather,
+            _ => &self.weather,
+        };
+        // Rendered images are cache files that are removed after the next change, hooks get
+        // the original images and the render only for immediate use.
+        let env = vec![
+            (
+                "FEHTHER_IMAGE",
+                self.shown.first().cloned().unwrap_or_default(),
+            ),
+            ("FEHTHER_IMAGES", self.shown.join("\n")),
+            (
+                "FEHTHER_RENDERED",
+                self.current_images.first().cloned().unwrap_or_default(),
+            ),
+            ("FEHTHER_DAYTIME", self.daytime.to_string()),
+            ("FEHTHER_WEATHER", weather.to_string()),
+            (
+                "FEHTHER_TEMPERATURE",
+                temperature.map_or(String::new(), |temperature| temperature.to_string()),
+            ),
+        ];
+        let timeout = std::time::Duration::from_secs(self.hooks.timeout);
+        if let Err(error) = hooks::run(command, event, env, timeout) {
+            eprintln!("fehther: {:#}", error);
         }
     }
 
-    // Set wallpaper
-    pub fn set_wallpaper(&self) -> Result<(), anyhow::Error> {
-        let directory_path = PathBuf::from(self.fetch_path());
-        if let Ok(entries) = fs::read_dir(&directory_path) {
-            let image_files: Vec<String> = entries
-                .filter_map(|entry| entry.ok().map(|e| e.path()))
-                .filter(|path| path.is_file())
-                .filter_map(|path| {
-                    path.extension()
-                        .and_then(|ext| ext.to_str())
-                        .filter(|ext| IMAGE_EXTENSIONS.contains(ext))
-                        .map(|_| path.display().to_string())
+    // If weather effects are on, draw the current weather onto the images.
+    fn render_effects(&self, images: Vec<String>, outputs: &[(u32, u32)]) -> Vec<String> {
+        if !self.effects.enabled {
+            return images;
+        }
+        let cache_dir = utils::fetch_cache_dir().join("effects");
+        images
+            .into_iter()
+            .enumerate()
+            .map(|(i, image)| {
+                match effects::render_cached(
+                    &image,
+                    outputs.get(i).copied(),
+                    &self.weather,
+                    self.precipitation,
+                    &self.effects,
+                    &cache_dir,
+                ) {
+                    Ok(rendered) => rendered.unwrap_or(image),
+                    Err(error) => {
+                        eprintln!("fehther: weather effect failed: {:#}", error);
+                        image
+                    }
+                }
+            })
+            .collect()
+    }
+
+    // Current overlay text, None until the first weather observation.
+    fn fetch_overlay_text(&self) -> Option<String> {
+        self.observation.as_ref().map(|observation| {
+            overlay::format_text(&self.overlay.format, observation, utils::now_secs() as i64)
+        })
+    }
+
+    // If the overlay is on, draw the current observation onto the images. The images are
+    // kept as base, so the text can be redrawn without picking new images.
+    fn render_overlay(&mut self, images: Vec<String>, outputs: &[(u32, u32)]) -> Vec<String> {
+        if !self.overlay.enabled {
+            return images;
+        }
+        self.overlay_base = images.clone();
+        self.overlay_outputs = outputs.to_vec();
+        let Some(text) = self.fetch_overlay_text() else {
+            return images;
+        };
+        self.overlay_text = text;
+        let font = match overlay::load_font(self.overlay.font.as_deref()) {
+            Ok(font) => font,
+            Err(error) => {
+                eprintln!("fehther: overlay failed: {:#}", error);
+                return images;
+            }
+        };
+        let cache_dir = utils::fetch_cache_dir().join("overlay");
+        images
+            .into_iter()
+            .enumerate()
+            .map(|(i, image)| {
+                overlay::render_cached(
+                    &image,
+                    outputs.get(i).copied(),
+                    &self.overlay_text,
+                    &font,
+                    &self.overlay,
+                    &cache_dir,
+                )
+                .unwrap_or_else(|error| {
+                    eprintln!("fehther: overlay failed: {:#}", error);
+                    image
                 })
-                .collect();
+            })
+            .collect()
+    }
 
-            if !image_files.is_empty() {
-                let mut command = Command::new("feh");
-                command.args([&self.feh_mode, "--randomize"]);
-                command.args(&image_files);
-                command.output().ok();
+    // If the overlay is on, redraw it onto the current images when its text has changed.
+    pub fn check_overlay(&mut self) {
+        if self.overlay_base.is_empty()
+            || self
+                .fetch_overlay_text()
+                .is_none_or(|text| text == self.overlay_text)
+        {
+            return;
+        }
+        let outputs = self.overlay_outputs.clone();
+        let images = self.render_overlay(self.overlay_base.clone(), &outputs);
+        match setter::set_images(self.backend, &self.feh_mode, &images, &self.transition) {
+            Ok(()) => {
+                self.current_images = images;
+                self.prune_renders();
             }
+            Err(error) => eprintln!("fehther: overlay failed: {:#}", error),
+        }
+    }
+
+    // Draw effects and overlay again onto the images on screen, without picking new ones.
+    fn redraw(&mut self) {
+        let outputs = utils::fetch_outputs();
+        let images = self.render_effects(self.shown.clone(), &outputs);
+        let images = self.render_overlay(images, &outputs);
+        match setter::set_images(self.backend, &self.feh_mode, &images, &self.transition) {
+            Ok(()) => {
+                self.current_images = images;
+                self.prune_renders();
+            }
+            Err(error) => eprintln!("fehther: weather effect failed: {:#}", error),
+        }
+    }
+
+    // Rendered effects and overlays are only kept while they are on screen or used as
+    // the base of the overlay, blends only while their scene is shown.
+    fn prune_renders(&self) {
+        let keep = [self.current_images.clone(), self.overlay_base.clone()].concat();
+        if self.effects.enabled {
+            effects::prune(&utils::fetch_cache_dir().join("effects"), &keep);
+        }
+        if self.overlay.enabled {
+            effects::prune(&utils::fetch_cache_dir().join("overlay"), &keep);
+        }
+        let scene = self
+            .blend_scene
+            .as_ref()
+            .map(|(day, night)| (day.as_str(), night.as_str()));
+        blend::prune(&utils::fetch_cache_dir().join("blend"), scene);
+    }
+
+    // Set wallpaper, one image per connected output.
+    pub fn set_wallpaper(&mut self) -> Result<(), anyhow::Error> {
+        self.debounce.changed(utils::now_secs());
+        if self.modes.contains(&Mode::Blend) {
+            return self.set_blended_wallpaper(true);
+        }
+        let library = self.open_library();
+        let Some((level, path, records)) = self.fetch_path(library.as_ref()) else {
+            eprintln!("fehther: no images found in any fallback folder");
+            return Ok(());
+        };
+        println!("fehther: using {} folder {}", level, path.display());
+
+        let outputs = utils::fetch_outputs();
+        // Without the library nothing was shown yet, so images are picked at random.
+        let selected = self.filter.select(records, &outputs);
+        if let Some(library) = &library {
+            let now = utils::now_secs();
+            for image in &selected {
+                library.mark_shown(image, now)?;
+            }
+        }
+        self.push_history();
+        self.show(selected, &outputs)
+    }
+
+    // Keep the images on screen for going back to them.
+    fn push_history(&mut self) {
+        if !self.shown.is_empty() {
+            self.history.push(std::mem::take(&mut self.shown));
+            if self.history.len() > HISTORY_LENGTH {
+                self.history.remove(0);
+            }
+        }
+    }
+
+    // Go back to the wallpaper before the current one.
+    pub fn previous_wallpaper(&mut self) -> Result<(), anyhow::Error> {
+        if self.modes.contains(&Mode::Blend) {
+            return Err(anyhow::anyhow!("blend mode has no previous wallpaper"));
+        }
+        let images = self
+            .history
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("no previous wallpaper"))?;
+        self.show(images, &utils::fetch_outputs())
+    }
+
+    // Use a folder ahead of the fallback chain, relative to the wallpaper path. An empty
+    // folder clears the override.
+    pub fn set_override(&mut self, folder: &str) -> Result<(), anyhow::Error> {
+        let folder = folder.trim_matches('/');
+        if folder.is_empty() {
+            self.override_folder = None;
+        } else if Path::new(&self.path).join(folder).is_dir() {
+            self.override_folder = Some(folder.to_string());
+        } else {
+            return Err(anyhow::anyhow!("no such folder: {}", folder));
+        }
+        self.current_loop = true;
+        Ok(())
+    }
+
+    // Draw effects and overlay onto picked images and show them.
+    fn show(&mut self, selected: Vec<String>, outputs: &[(u32, u32)]) -> Result<(), anyhow::Error> {
+        let images = self.render_effects(selected.clone(), outputs);
+        let images = self.render_overlay(images, outputs);
+        // swww fades on its own, feh gets blended frames rendered by fehther.
+        if self.transition.crossfade && self.backend == Backend::Feh {
+            let frame_dir = utils::fetch_cache_dir().join("transition");
+            // The fade sleeps between frames, keep the other tasks such as D-Bus running meanwhile.
+            tokio::task::block_in_place(|| {
+                transition::crossfade(
+                    &self.current_images,
+                    &images,
+                    outputs,
+                    &self.transition,
+                    &frame_dir,
+                    |frames| {
+                        setter::set_images(self.backend, &self.feh_mode, frames, &self.transition)
+                    },
+                )
+            })
+            .unwrap_or_else(|error| eprintln!("fehther: crossfade failed: {:#}", error));
         }
+        setter::set_images(self.backend, &self.feh_mode, &images, &self.transition)?;
+        self.current_images = images;
+        if let Some(image) = selected.first() {
+            self.update_palette(image);
+        }
+        self.shown = selected;
+        self.run_hook(Event::Wallpaper, None);
+        self.prune_renders();
         Ok(())
     }
 }
+
+// Where images of the fallback chain come from: the library, or the tag index and image
+// header cache with a plain folder scan when the library cannot be opened.
+enum ImageSource<'a> {
+    Library(&'a Library),
+    Scan(Option<TagIndex>, Option<InfoCache>),
+}
+
+impl<'a> ImageSource<'a> {
+    fn new(settings: &Settings, library: Option<&'a Library>) -> ImageSource<'a> {
+        match library {
+            Some(library) => ImageSource::Library(library),
+            None => ImageSource::Scan(settings.fetch_tag_index(), settings.fetch_info_cache()),
+        }
+    }
+
+    fn fetch_images(&mut self, settings: &Settings, folder: &Path) -> Vec<Record> {
+        match self {
+            ImageSource::Library(library) => settings.fetch_images(library, folder),
+            ImageSource::Scan(index, cache) => {
+                settings.fetch_scanned_images(folder, index.as_ref(), cache.as_mut())
+            }
+        }
+    }
+
+    // Keep image headers read during the scan.
+    fn save(&self) {
+        if let ImageSource::Scan(_, Some(cache)) = self {
+            cache.save(&InfoCache::cache_file()).ok();
+        }
+    }
+}
+
+#[test]
+// Without the library, images come from a folder scan or the tag index in tag mode
+fn fetch_path_without_library_test() -> Result<(), anyhow::Error> {
+    let mut settings = crate::config::fetch_config(PathBuf::from(r"./test_config/test_1.ini"))?;
+    let dir = tempfile::tempdir()?;
+    std::fs::create_dir(dir.path().join("boo"))?;
+    std::fs::write(dir.path().join("boo/lake.jpg"), b"")?;
+    std::fs::write(dir.path().join("boo_rain.jpg"), b"")?;
+    settings.path = dir.path().display().to_string();
+    settings.index_file = dir.path().join(".fehther-index.json");
+    settings.modes = vec![Mode::Daytime];
+    settings.daytime = Daytime::Night;
+
+    let (level, path, images) = settings.fetch_path(None).unwrap();
+    assert_eq!(level, FallbackLevel::Daytime);
+    assert_eq!(path, dir.path().join("boo"));
+    assert_eq!(
+        images,
+        vec![Record::new(
+            dir.path().join("boo/lake.jpg").display().to_string()
+        )]
+    );
+
+    // In tag mode the index is built next to the wallpapers.
+    settings.tag_mode = true;
+    let (_, _, images) = settings.fetch_path(None).unwrap();
+    assert_eq!(
+        images,
+        vec![Record::new(
+            dir.path().join("boo_rain.jpg").display().to_string()
+        )]
+    );
+    assert!(settings.index_file.exists());
+    Ok(())
+}
+
+#[test]
+// The weather rule is listed with the weather, ahead of recovery mode
+fn explain_header_test() -> Result<(), anyhow::Error> {
+    let mut settings = crate::config::fetch_config(PathBuf::from(r"./test_config/test_5.ini"))?;
+    settings.path = tempfile::tempdir()?.path().display().to_string();
+    settings.weather_rule = Some("hot".to_string());
+    let report = settings.explain(Some(&Library::open_in_memory()?));
+    assert!(
+        report.starts_with(&format!(
+            "daytime: {}\nweather: {}\nweather rule: hot\nrecovery mode: false\n\nfallback chain:\n",
+            settings.daytime, settings.weather
+        )),
+        "{}",
+        report
+    );
+    Ok(())
+}
+
+#[test]
+// An override goes ahead of the whole fallback chain until cleared
+fn set_override_test() -> Result<(), anyhow::Error> {
+    let mut settings = crate::config::fetch_config(PathBuf::from(r"./test_config/test_1.ini"))?;
+    let dir = tempfile::tempdir()?;
+    std::fs::create_dir(dir.path().join("holiday"))?;
+    settings.path = dir.path().display().to_string();
+    settings.current_loop = false;
+    settings.set_override("holiday/")?;
+    assert!(settings.current_loop);
+    assert_eq!(
+        settings.fetch_fallback_chain().first(),
+        Some(&(FallbackLevel::Override, dir.path().join("holiday")))
+    );
+    assert!(settings.set_override("missing").is_err());
+    assert_eq!(settings.override_folder.as_deref(), Some("holiday"));
+    settings.set_override("")?;
+    assert!(settings.override_folder.is_none());
+    assert!(settings.previous_wallpaper().is_err());
+    Ok(())
+}
+
+#[test]
+// testing fallback chain order, including nested weather groups
+fn fetch_fallback_chain_test() -> Result<(), anyhow::Error> {
+    let mut settings = crate::config::fetch_config(PathBuf::from(r"./test_config/test_1.ini"))?;
+    let root = PathBuf::from("/home/user/files/documents/wallpapers");
+    settings.daytime = Daytime::Day;
+    settings.weather = WeatherType::Rain;
+
+    let expected = vec![
+        (FallbackLevel::Weather, root.join("foo/rainy")),
+        (FallbackLevel::Daytime, root.join("foo")),
+        (FallbackLevel::Default, root.join("fallback")),
+        (FallbackLevel::Root, root.clone()),
+    ];
+    assert_eq!(settings.fetch_fallback_chain(), expected);
+
+    settings
+        .weather_groups
+        .insert("snow/heavy".to_string(), vec![WeatherType::Snow]);
+    settings.weather = WeatherType::Snow;
+    let expected = vec![
+        (FallbackLevel::Weather, root.join("foo/snow/heavy")),
+        (FallbackLevel::WeatherParent, root.join("foo/snow")),
+        (FallbackLevel::Daytime, root.join("foo")),
+        (FallbackLevel::Default, root.join("fallback")),
+        (FallbackLevel::Root, root.clone()),
+    ];
+    assert_eq!(settings.fetch_fallback_chain(), expected);
+
+    // A matching weather rule is tried before the weather group.
+    settings.weather_rule = Some("hot".to_string());
+    assert_eq!(
+        settings.fetch_fallback_chain()[..2],
+        [
+            (FallbackLevel::Rule, root.join("foo/hot")),
+            (FallbackLevel::Weather, root.join("foo/snow/heavy")),
+        ]
+    );
+    settings.weather_rule = None;
+
+    // Heavy rain is not in a group, so it uses the group containing rain.
+    settings.weather = WeatherType::HeavyRain;
+    let expected = vec![
+        (FallbackLevel::Weather, root.join("foo/rainy")),
+        (FallbackLevel::Daytime, root.join("foo")),
+        (FallbackLevel::Default, root.join("fallback")),
+        (FallbackLevel::Root, root.clone()),
+    ];
+    assert_eq!(settings.fetch_fallback_chain(), expected);
+    settings.weather = WeatherType::Snow;
+
+    // Sunset is disabled for weather mode, recovery mode skips straight to the default folder.
+    settings.daytime = Daytime::Sunset;
+    assert_eq!(settings.fetch_fallback_chain()[0].1, root.join("woo"));
+    settings.recovery_mode = true;
+    assert_eq!(settings.fetch_fallback_chain()[0].1, root.join("fallback"));
+    Ok(())
+}
+
+#[test]
+// testing that flapping between weather types of one group keeps the wallpaper
+fn check_weather_mode_same_group_test() -> Result<(), anyhow::Error> {
+    let mut settings = crate::config::fetch_config(PathBuf::from(r"./test_config/test_1.ini"))?;
+    settings.debounce.settings.ignore_same_group = true;
+    let response = |id: i32| -> crate::fetch_weather::WeatherResponse {
+        serde_json::from_str(&format!(
+            r#"{{"weather": [{{"id": {}, "main": ""}}], "sys": {{"sunrise": 0, "sunset": 0}}}}"#,
+            id
+        ))
+        .unwrap()
+    };
+    settings.check_weather_mode(&response(803).observation());
+    assert_eq!(settings.weather, WeatherType::BrokenClouds);
+    settings.current_loop = false;
+    for id in [804, 803, 804] {
+        settings.check_weather_mode(&response(id).observation());
+        assert!(!settings.current_loop);
+    }
+    assert_eq!(settings.weather, WeatherType::OvercastClouds);
+    settings.check_weather_mode(&response(500).observation());
+    assert!(settings.current_loop);
+    Ok(())
+}
+
+#[test]
+// testing that rain intensities sharing a folder keep the wallpaper
+fn check_weather_mode_intensity_test() -> Result<(), anyhow::Error> {
+    let mut settings = crate::config::fetch_config(PathBuf::from(r"./test_config/test_1.ini"))?;
+    let dir = tempfile::tempdir()?;
+    settings.path = dir.path().display().to_string();
+    settings.custom_weather_groups = false;
+    settings.daytime = Daytime::Day;
+    std::fs::create_dir_all(dir.path().join("foo/rain"))?;
+    let observation = |weather| Observation {
+        weather: Some(weather),
+        ..Default::default()
+    };
+    settings.check_weather_mode(&observation(WeatherType::Rain));
+    settings.current_loop = false;
+    // OpenWeatherMap codes 500 and 501 both end up in the rain folder.
+    for weather in [
+        WeatherType::LightRain,
+        WeatherType::Rain,
+        WeatherType::LightRain,
+    ] {
+        settings.check_weather_mode(&observation(weather.clone()));
+        assert!(!settings.current_loop);
+        assert_eq!(settings.weather, weather);
+    }
+    // A folder of its own for light rain does change the wallpaper.
+    std::fs::create_dir_all(dir.path().join("foo/light rain"))?;
+    settings.check_weather_mode(&observation(WeatherType::Rain));
+    assert!(settings.current_loop);
+    settings.current_loop = false;
+    settings.check_weather_mode(&observation(WeatherType::Snow));
+    assert!(settings.current_loop);
+    Ok(())
+}
+
+#[test]
+// Debounced weather changes within the same group still run the weather hook
+fn check_weather_mode_same_group_hook_test() -> Result<(), anyhow::Error> {
+    let mut settings = crate::config::fetch_config(PathBuf::from(r"./test_config/test_1.ini"))?;
+    settings.debounce.settings.ignore_same_group = true;
+    settings.weather = WeatherType::BrokenClouds;
+    let dir = tempfile::tempdir()?;
+    let output = dir.path().join("weather");
+    settings.hooks.on_weather_change =
+        Some(format!("echo \"$FEHTHER_WEATHER\" > {}", output.display()));
+    settings.debounce.settings.polls = 2;
+    let observation = |weather| Observation {
+        weather: Some(weather),
+        ..Default::default()
+    };
+    settings.check_weather_mode(&observation(WeatherType::BrokenClouds));
+    settings.current_loop = false;
+    // A single flip is held back by the stability settings and runs no hook.
+    settings.check_weather_mode(&observation(WeatherType::OvercastClouds));
+    settings.check_weather_mode(&observation(WeatherType::BrokenClouds));
+    assert_eq!(settings.weather, WeatherType::BrokenClouds);
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    assert!(!output.exists());
+    settings.check_weather_mode(&observation(WeatherType::OvercastClouds));
+    settings.check_weather_mode(&observation(WeatherType::OvercastClouds));
+    assert_eq!(settings.weather, WeatherType::OvercastClouds);
+    assert!(!settings.current_loop);
+    let started = std::time::Instant::now();
+    while !output.exists() && started.elapsed() < std::time::Duration::from_secs(5) {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    assert_eq!(std::fs::read_to_string(&output)?, "overcast clouds\n");
+    Ok(())
+}
+
+#[test]
+// Weather effects without weather mode keep the wallpaper and only follow the weather
+fn check_effects_test() -> Result<(), anyhow::Error> {
+    let mut settings = crate::config::fetch_config(PathBuf::from(r"./test_config/test_1.ini"))?;
+    settings.effects.enabled = true;
+    settings.current_loop = false;
+    let rain = Observation {
+        weather: Some(WeatherType::Rain),
+        rain: 1.5,
+        ..Default::default()
+    };
+    settings.paused = true;
+    settings.check_effects(&rain);
+    assert_eq!(settings.weather, WeatherType::Clear);
+    settings.paused = false;
+    settings.check_effects(&rain);
+    assert_eq!(settings.weather, WeatherType::Rain);
+    assert_eq!(settings.precipitation, 1.5);
+    assert!(!settings.current_loop);
+    Ok(())
+}
+
+#[test]
+// testing that a response without conditions or sun times keeps the current state
+fn check_partial_response_test() -> Result<(), anyhow::Error> {
+    let mut settings = crate::config::fetch_config(PathBuf::from(r"./test_config/test_1.ini"))?;
+    settings.weather = WeatherType::Snow;
+    settings.daytime = Daytime::Night;
+    settings.current_loop = false;
+    let response: crate::fetch_weather::WeatherResponse =
+        serde_json::from_str(include_str!("../test_fixtures/weather_partial.json"))?;
+    settings.check_weather_mode(&response.observation());
+    settings.check_daytime_mode(&response.observation());
+    assert_eq!(settings.weather, WeatherType::Snow);
+    assert_eq!(settings.daytime, Daytime::Night);
+    assert!(!settings.current_loop);
+    Ok(())
+}
+
+#[test]
+// testing that location following waits for a minimum distance
+fn check_location_test() -> Result<(), anyhow::Error> {
+    use crate::location_source::MockSource;
+    let mut settings = crate::config::fetch_config(PathBuf::from(r"./test_config/test_1.ini"))?;
+    settings.follow = Some(LocationFollower {
+        source: Box::new(MockSource(vec![
+            None,
+            Some((52.3676, 4.9041)),
+            Some((52.37, 4.91)),
+            Some((52.0907, 5.1214)),
+        ])),
+        min_distance: 5.0,
+        interval: 1,
+        timer: 0,
+    });
+    settings.current_loop = false;
+    // Unknown position keeps the configured city.
+    settings.check_location();
+    assert!(matches!(settings.location, Location::City { .. }));
+    settings.check_location();
+    assert_eq!(settings.timezone.as_deref(), Some("Europe/Amsterdam"));
+    assert!(settings.current_loop);
+    settings.current_loop = false;
+    // Less than a kilometre is ignored.
+    settings.check_location();
+    assert!(!settings.current_loop);
+    settings.check_location();
+    assert_eq!(
+        settings.location,
+        Location::Coordinates {
+            lat: 52.0907,
+            lon: 5.1214
+        }
+    );
+    assert!(settings.current_loop);
+    Ok(())
+}
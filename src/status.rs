@@ -0,0 +1,276 @@
+use crate::overlay;
+use crate::settings::Settings;
+use crate::utils;
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+// State of the running daemon, written for `fehther status` whenever it changes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct State {
+    pub pid: u32,
+    pub daytime: String,
+    pub weather: String,
+    pub icon: String,
+    pub temperature: Option<f64>,
+    pub location: String,
+    // Image picked for the first output, before effects and overlay.
+    pub image: String,
+    pub paused: bool,
+}
+
+impl State {
+    pub fn from_settings(settings: &Settings) -> State {
+        let observation = settings.observation.as_ref();
+        let weather = settings.current_weather();
+        State {
+            pid: std::process::id(),
+            daytime: settings.daytime.to_string().to_lowercase(),
+            weather: weather.to_string(),
+            icon: overlay::icon(weather).to_string(),
+            temperature: observation.and_then(|observation| observation.temperature),
+            location: observation.map_or(String::new(), |observation| observation.location.clone()),
+            image: settings.shown.first().cloned().unwrap_or_default(),
+            paused: settings.paused,
+        }
+    }
+
+    // File name of the image, without folder and extension.
+    fn image_name(&self) -> &str {
+        Path::new(&self.image)
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+    }
+
+    // Icon and temperature, e.g. "☂ 11°C".
+    fn short_text(&self) -> String {
+        match self.temperature {
+            Some(temperature) => format!("{} {:.0}°C", self.icon, temperature),
+            None => self.icon.clone(),
+        }
+    }
+
+    // Short text with daytime and wallpaper name.
+    fn full_text(&self) -> String {
+        let mut text = format!("{} {}", self.short_text(), self.daytime);
+        if !self.image.is_empty() {
+            text += &format!(" · {}", self.image_name());
+        }
+        if self.paused {
+            text += " (paused)";
+        }
+        text
+    }
+
+    fn tooltip(&self) -> String {
+        let mut lines = vec![format!("{}, {}", self.weather, self.daytime)];
+        if !self.location.is_empty() {
+            lines.push(self.location.clone());
+        }
+        if !self.image.is_empty() {
+            lines.push(self.image.clone());
+        }
+        lines.join("\n")
+    }
+
+    // One line of output for a status bar.
+    pub fn format(&self, format: Format) -> String {
+        match format {
+            // Polybar shows the lines of a script as they are.
+            Format::Plain | Format::Polybar => self.full_text(),
+            // Waybar custom module with return-type = json.
+            Format::Waybar => {
+                let mut class = vec![self.daytime.clone()];
+                if self.paused {
+                    class.push("paused".to_string());
+                }
+                json!({
+                    "text": self.full_text(),
+                    "alt": self.weather,
+                    "tooltip": self.tooltip(),
+                    "class": class,
+                })
+                .to_string()
+            }
+            // i3blocks block with format = json.
+            Format::I3blocks => json!({
+                "full_text": self.full_text(),
+                "short_text": self.short_text(),
+            })
+            .to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Plain,
+    Waybar,
+    Polybar,
+    I3blocks,
+}
+
+impl Format {
+    pub fn from_string(format: &str) -> Result<Format> {
+        match format {
+            "plain" => Ok(Format::Plain),
+            "waybar" => Ok(Format::Waybar),
+            "polybar" => Ok(Format::Polybar),
+            "i3blocks" => Ok(Format::I3blocks),
+            _ => Err(anyhow!("not a known status format: {}", format)),
+        }
+    }
+}
+
+// State file of the daemon.
+pub fn fetch_state_path() -> PathBuf {
+    utils::fetch_runtime_dir().join("state.json")
+}
+
+// Write the state, if it differs from the file. The file is replaced at once, so readers
+// never see half of it.
+pub fn write(path: &Path, state: &State) -> Result<()> {
+    let text = serde_json::to_string(state)?;
+    if fs::read_to_string(path).is_ok_and(|current| current == text) {
+        return Ok(());
+    }
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir).with_context(|| format!("Could not create {}", dir.display()))?;
+    let partial = path.with_extension("json.partial");
+    fs::write(&partial, text)?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+// Read the state, None when the daemon is not running.
+pub fn read(path: &Path) -> Option<State> {
+    let state: State = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    // A daemon that was killed leaves its state behind.
+    Path::new("/proc")
+        .join(state.pid.to_string())
+        .exists()
+        .then_some(state)
+}
+
+// Print the state once, or with --follow a line whenever it changes.
+pub fn status_command(args: &[String]) -> Result<()> {
+    let usage =
+        || anyhow!("usage: fehther status [--format plain|waybar|polybar|i3blocks] [--follow]");
+    let mut format = Format::Plain;
+    let mut follow = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--follow" | "-f" => follow = true,
+            "--format" => format = Format::from_string(args.next().ok_or_else(usage)?)?,
+            arg => match arg.strip_prefix("--format=") {
+                Some(name) => format = Format::from_string(name)?,
+                None => return Err(usage()),
+            },
+        }
+    }
+    let path = fetch_state_path();
+    if !follow {
+        let state = read(&path).ok_or_else(|| anyhow!("no running fehther found"))?;
+        println!("{}", state.format(format));
+        return Ok(());
+    }
+    // Bars keep the last line, an empty line clears it while the daemon is away.
+    let mut last = None;
+    loop {
+        let line = read(&path).map_or(String::new(), |state| state.format(format));
+        if last.as_ref() != Some(&line) {
+            println!("{}", line);
+            last = Some(line);
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> State {
+        State {
+            pid: std::process::id(),
+            daytime: "night".to_string(),
+            weather: "light rain".to_string(),
+            icon: "☂".to_string(),
+            temperature: Some(11.3),
+            location: "Amsterdam, NL".to_string(),
+            image: "/wallpapers/night/rain/canal.jpg".to_string(),
+            paused: false,
+        }
+    }
+
+    #[test]
+    fn test_formats() {
+        let mut state = state();
+        assert_eq!(state.format(Format::Plain), "☂ 11°C night · canal");
+        assert_eq!(state.format(Format::Polybar), "☂ 11°C night · canal");
+        assert_eq!(
+            state.format(Format::I3blocks),
+            r#"{"full_text":"☂ 11°C night · canal","short_text":"☂ 11°C"}"#
+        );
+        state.paused = true;
+        let waybar: serde_json::Value =
+            serde_json::from_str(&state.format(Format::Waybar)).unwrap();
+        assert_eq!(waybar["text"], "☂ 11°C night · canal (paused)");
+        assert_eq!(waybar["alt"], "light rain");
+        assert_eq!(
+            waybar["tooltip"],
+            "light rain, night\nAmsterdam, NL\n/wallpapers/night/rain/canal.jpg"
+        );
+        assert_eq!(waybar["class"], json!(["night", "paused"]));
+        state.temperature = None;
+        state.image.clear();
+        assert_eq!(state.format(Format::Plain), "☂ night (paused)");
+        assert!(Format::from_string("xmobar").is_err());
+    }
+
+    #[test]
+    fn test_from_settings() {
+        let mut settings =
+            crate::config::fetch_config(std::path::PathBuf::from(r"./test_config/test_1.ini"))
+                .unwrap();
+        assert_eq!(State::from_settings(&settings).weather, "clear");
+        // Outside weather mode settings.weather is not updated, the observation is used.
+        settings
+            .modes
+            .retain(|mode| *mode != crate::types::modes::Mode::Weather);
+        settings.observation = Some(crate::fetch_weather::Observation {
+            weather: Some(crate::types::weathertype::WeatherType::LightRain),
+            temperature: Some(11.3),
+            ..Default::default()
+        });
+        let state = State::from_settings(&settings);
+        assert_eq!(state.weather, "light rain");
+        assert_eq!(
+            state.icon,
+            overlay::icon(&crate::types::weathertype::WeatherType::LightRain)
+        );
+        assert_eq!(state.temperature, Some(11.3));
+    }
+
+    #[test]
+    fn test_state_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fehther").join("state.json");
+        assert_eq!(read(&path), None);
+        write(&path, &state()).unwrap();
+        assert_eq!(read(&path), Some(state()));
+        // Left behind by a daemon that is gone.
+        let gone = State {
+            pid: u32::MAX,
+            ..state()
+        };
+        write(&path, &gone).unwrap();
+        assert_eq!(read(&path), None);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::scan::{self, ScanOptions};
+use crate::utils;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path};
+
+// Only the start of a file is searched for an XMP packet, keeps indexing large libraries fast.
+const XMP_SEARCH_LIMIT: u64 = 256 * 1024;
+
+// Tag index stored next to the wallpapers, built by `fehther index`. Used in place of the
+// library when its database cannot be opened.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct TagIndex {
+    pub images: BTreeMap<String, IndexEntry>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct IndexEntry {
+    pub modified: u64,
+    pub tags: Vec<String>,
+    // Tags added by hand in the index file, kept when the image is re-indexed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom: Vec<String>,
+}
+
+impl IndexEntry {
+    fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter()
+            .all(|tag| self.tags.contains(tag) || self.custom.contains(tag))
+    }
+}
+
+impl TagIndex {
+    // Load index from disk, a missing or unreadable index starts empty.
+    pub fn load(index_file: &Path) -> TagIndex {
+        fs::read_to_string(index_file)
+            .ok()
+            .and_then(|body| serde_json::from_str(&body).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, index_file: &Path) -> Result<()> {
+        fs::write(index_file, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write tag index: {}", index_file.display()))
+    }
+
+    // Bring the index up to date with the library. Only new and modified images are read,
+    // removed images are dropped. Returns the number of updated and removed entries.
+    pub fn refresh(&mut self, root: &Path, options: &ScanOptions) -> (usize, usize) {
+        let images = scan::find_images(root, options);
+        let before = self.images.len();
+        let found: HashSet<&str> = images.iter().map(String::as_str).collect();
+        self.images.retain(|path, _| found.contains(path.as_str()));
+        let removed = before - self.images.len();
+
+        let mut updated = 0;
+        for image in images {
+            let path = Path::new(&image);
+            let modified = utils::modified_secs(path);
+            if self
+                .images
+                .get(&image)
+                .is_some_and(|entry| entry.modified == modified)
+            {
+                continue;
+            }
+            let mut tags = path_tags(root, path);
+            tags.extend(xmp_tags(path));
+            tags.sort();
+            tags.dedup();
+            let custom = self
+                .images
+                .remove(&image)
+                .map(|entry| entry.custom)
+                .unwrap_or_default();
+            self.images.insert(
+                image,
+                IndexEntry {
+                    modified,
+                    tags,
+                    custom,
+                },
+            );
+            updated += 1;
+        }
+        (updated, removed)
+    }
+
+    // Find all images carrying every one of the given tags.
+    pub fn matching(&self, tags: &[String]) -> Vec<String> {
+        self.images
+            .iter()
+            .filter(|(_, entry)| entry.has_tags(tags))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+}
+
+// Tags are compared lowercase, with spaces replaced by dashes to match config names.
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase().replace(' ', "-")
+}
+
+// Tags required for a folder of the fallback chain, e.g. root/day/rain -> [day, rain].
+pub fn folder_tags(root: &Path, folder: &Path) -> Vec<String> {
+    folder
+        .strip_prefix(root)
+        .map(|relative| {
+            relative
+                .components()
+                .filter_map(|component| match component {
+                    Component::Normal(name) => name.to_str().map(normalize_tag),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Tags from the filename and sub folders, e.g. night_rain_01.jpg -> [night, rain, 01].
+pub fn path_tags(root: &Path, image: &Path) -> Vec<String> {
+    let mut tags = image
+        .parent()
+        .map_or_else(Vec::new, |parent| folder_tags(root, parent));
+    if let Some(stem) = image.file_stem().and_then(|stem| stem.to_str()) {
+        tags.extend(
+            stem.split(['_', ' ', '.', ','])
+                .filter(|token| !token.is_empty())
+                .map(normalize_tag),
+        );
+    }
+    tags
+}
+
+// Keywords from an embedded XMP packet (dc:subject), as written by most photo managers.
+pub fn xmp_tags(image: &Path) -> Vec<String> {
+    let mut buffer = Vec::new();
+    if fs::File::open(image)
+        .and_then(|file| file.take(XMP_SEARCH_LIMIT).read_to_end(&mut buffer))
+        .is_err()
+    {
+        return Vec::new();
+    }
+    parse_xmp_subjects(&String::from_utf8_lossy(&buffer))
+}
+
+fn parse_xmp_subjects(xmp: &str) -> Vec<String> {
+    let Some(start) = xmp.find("<dc:subject>") else {
+        return Vec::new();
+    };
+    let subject = &xmp[start..];
+    let subject = &subject[..subject.find("</dc:subject>").unwrap_or(subject.len())];
+    subject
+        .split("<rdf:li>")
+        .skip(1)
+        .filter_map(|item| item.split("</rdf:li>").next())
+        .map(normalize_tag)
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_path_tags() {
+        let root = PathBuf::from("/wallpapers");
+        let tags = path_tags(&root, &root.join("Night_Rain_very-cloudy.jpg"));
+        assert_eq!(tags, vec!["night", "rain", "very-cloudy"]);
+        let tags = path_tags(&root, &root.join("day/lake.png"));
+        assert_eq!(tags, vec!["day", "lake"]);
+    }
+
+    #[test]
+    fn test_parse_xmp_subjects() {
+        let xmp = "<x:xmpmeta><dc:subject><rdf:Bag><rdf:li>Night</rdf:li>\
+                   <rdf:li>Scattered Clouds</rdf:li></rdf:Bag></dc:subject>\
+                   <dc:creator><rdf:Seq><rdf:li>someone</rdf:li></rdf:Seq></dc:creator>";
+        assert_eq!(parse_xmp_subjects(xmp), vec!["night", "scattered-clouds"]);
+        assert!(parse_xmp_subjects("no metadata").is_empty());
+    }
+
+    #[test]
+    fn test_matching() {
+        let mut index = TagIndex::default();
+        index.images.insert(
+            "a.jpg".to_string(),
+            IndexEntry {
+                modified: 0,
+                tags: vec!["night".to_string(), "rain".to_string()],
+                custom: vec!["snow".to_string()],
+            },
+        );
+        index.images.insert(
+            "b.jpg".to_string(),
+            IndexEntry {
+                modified: 0,
+                tags: vec!["day".to_string(), "rain".to_string()],
+                custom: vec![],
+            },
+        );
+        let tags = |tags: &[&str]| tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        assert_eq!(index.matching(&tags(&["night", "rain"])), vec!["a.jpg"]);
+        assert_eq!(index.matching(&tags(&["night", "snow"])), vec!["a.jpg"]);
+        assert_eq!(index.matching(&tags(&["rain"])).len(), 2);
+        assert!(index.matching(&tags(&["day", "snow"])).is_empty());
+    }
+}
@@ -0,0 +1,55 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+// Answer a single HTTP request with the given status and JSON body, returns the base url.
+pub fn serve(status: &'static str, body: &'static str) -> String {
+    serve_paths(vec![("", status, body)]).0
+}
+
+// Answer one request for each (path, status, body), matched on the start of the request
+// path. Each route answers once, in order, so a path can get different answers. Returns the base url and the received request heads, for checking headers.
+pub fn serve_paths(
+    mut routes: Vec<(&'static str, &'static str, &'static str)>,
+) -> (String, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let (sender, requests) = mpsc::channel();
+    let count = routes.len();
+    thread::spawn(move || {
+        for _ in 0..count {
+            let (mut stream, _) = listener.accept().unwrap();
+            // Read up to the blank line ending the request headers.
+            let mut reader = BufReader::new(&stream);
+            let mut head = String::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok_and(|read| read > 2) {
+                head.push_str(&line);
+                line.clear();
+            }
+            let path = head
+                .split_whitespace()
+                .nth(1)
+                .unwrap_or_default()
+                .to_string();
+            let (_, status, body) = match routes
+                .iter()
+                .position(|(route, _, _)| path.starts_with(route))
+            {
+                Some(index) => routes.remove(index),
+                None => ("", "404 Not Found", r#"{"message": "Not found"}"#),
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+            sender.send(head).ok();
+        }
+    });
+    (format!("http://{}", address), requests)
+}
@@ -0,0 +1,157 @@
+use anyhow::Result;
+use image::RgbImage;
+use image::imageops::FilterType;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+// Crossfade settings, parsed from the [transition] section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    pub crossfade: bool,
+    // Duration of the whole fade in seconds.
+    pub duration: f32,
+    // Number of steps, including the final image.
+    pub frames: u32,
+}
+
+impl Default for Transition {
+    fn default() -> Self {
+        Transition {
+            crossfade: false,
+            duration: 1.5,
+            frames: 12,
+        }
+    }
+}
+
+// Linear blend of two equally sized images into `out`, weight 0 is `from` and 1 is `to`.
+pub fn blend_into(from: &RgbImage, to: &RgbImage, weight: f32, out: &mut RgbImage) {
+    let weight = weight.clamp(0.0, 1.0);
+    for ((pixel, a), b) in out.iter_mut().zip(from.as_raw()).zip(to.as_raw()) {
+        *pixel = (*a as f32 + (*b as f32 - *a as f32) * weight).round() as u8;
+    }
+}
+
+pub fn blend(from: &RgbImage, to: &RgbImage, weight: f32) -> RgbImage {
+    let mut out = RgbImage::new(from.width(), from.height());
+    blend_into(from, to, weight, &mut out);
+    out
+}
+
+// Load an image, scaled and cropped to fill the given size.
+pub fn load_fitted(image: &Path, (width, height): (u32, u32)) -> Result<RgbImage> {
+    Ok(image::open(image)?
+        .resize_to_fill(width, height, FilterType::Triangle)
+        .to_rgb8())
+}
+
+// Fade from the old to the new images, one pair per output. Frames are rendered one at a time
+// into a reused buffer and handed to `show`, so memory stays at three images per output.
+// The final image is not shown, that is left to the caller.
+pub fn crossfade(
+    from: &[String],
+    to: &[String],
+    outputs: &[(u32, u32)],
+    transition: &Transition,
+    frame_dir: &Path,
+    mut show: impl FnMut(&[String]) -> Result<()>,
+) -> Result<()> {
+    let Some(first_from) = from.first() else {
+        return Ok(());
+    };
+    fs::create_dir_all(frame_dir)?;
+    let mut pairs = Vec::new();
+    for (i, image) in to.iter().enumerate() {
+        // Without known outputs, frames are rendered at the size of the new image.
+        let size = match outputs.get(i).or(outputs.first()) {
+            Some(&size) => size,
+            None => image::image_dimensions(image)?,
+        };
+        let previous = from.get(i).unwrap_or(first_from);
+        pairs.push((
+            load_fitted(Path::new(previous), size)?,
+            load_fitted(Path::new(image), size)?,
+        ));
+    }
+    let mut buffers: Vec<RgbImage> = pairs
+        .iter()
+        .map(|(image, _)| RgbImage::new(image.width(), image.height()))
+        .collect();
+
+    let frames = transition.frames.max(1);
+    let delay =
+        Duration::try_from_secs_f32(transition.duration / frames as f32).unwrap_or_default();
+    for step in 1..frames {
+        let weight = step as f32 / frames as f32;
+        let mut paths = Vec::new();
+        for (i, ((previous, next), buffer)) in pairs.iter().zip(buffers.iter_mut()).enumerate() {
+            blend_into(previous, next, weight, buffer);
+            // Alternate between two files so the setter never reads a half-written frame.
+            let path = frame_dir.join(format!("frame-{}-{}.bmp", i, step % 2));
+            buffer.save(&path)?;
+            paths.push(path.display().to_string());
+        }
+        show(&paths)?;
+        thread::sleep(delay);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn test_blend() {
+        let black = RgbImage::from_pixel(2, 1, Rgb([0, 0, 0]));
+        let white = RgbImage::from_pixel(2, 1, Rgb([255, 200, 100]));
+        assert_eq!(blend(&black, &white, 0.0), black);
+        assert_eq!(blend(&black, &white, 1.0), white);
+        assert_eq!(
+            blend(&black, &white, 0.25).get_pixel(1, 0),
+            &Rgb([64, 50, 25])
+        );
+        assert_eq!(
+            blend(&white, &black, 0.5).get_pixel(0, 0),
+            &Rgb([128, 100, 50])
+        );
+    }
+
+    #[test]
+    fn test_crossfade_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("from.png");
+        let to = dir.path().join("to.png");
+        RgbImage::from_pixel(8, 4, Rgb([0, 0, 0]))
+            .save(&from)
+            .unwrap();
+        RgbImage::from_pixel(16, 8, Rgb([200, 200, 200]))
+            .save(&to)
+            .unwrap();
+        let transition = Transition {
+            crossfade: true,
+            duration: 0.0,
+            frames: 4,
+        };
+
+        let mut shown = Vec::new();
+        crossfade(
+            &[from.display().to_string()],
+            &[to.display().to_string()],
+            &[(4, 2)],
+            &transition,
+            &dir.path().join("frames"),
+            |frames| {
+                let frame = image::open(&frames[0])?.to_rgb8();
+                assert_eq!(frame.dimensions(), (4, 2));
+                shown.push(frame.get_pixel(0, 0)[0]);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(shown, vec![50, 100, 150]);
+    }
+}
@@ -0,0 +1,29 @@
+use crate::fmt;
+use anyhow::anyhow;
+// Programs used to set the wallpaper.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Backend {
+    Feh,
+    Swww,
+}
+
+// fmt trait for logging
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Backend::Feh => write!(f, "feh"),
+            Backend::Swww => write!(f, "swww"),
+        }
+    }
+}
+
+impl Backend {
+    // Get backend from str
+    pub fn from_string(backend: &str) -> Result<Backend, anyhow::Error> {
+        match backend.to_lowercase().as_str() {
+            "feh" => Ok(Backend::Feh),
+            "swww" => Ok(Backend::Swww),
+            _ => Err(anyhow!("not a known backend: {}", backend)),
+        }
+    }
+}
@@ -1,2 +1,17 @@
 // Image types for finding image files
 pub const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "bmp", "webp", "tiff", "tif"];
+
+// Minutes between forecast requests in forecast mode
+pub const FORECAST_INTERVAL: i32 = 30;
+
+// Polls without weather requests after the provider's rate limit was reached
+pub const QUOTA_BACKOFF: i32 = 15;
+
+// IP geolocation service used for location = ip
+pub const GEOLOCATION_URL: &str = "http://ip-api.com/json";
+
+// Earlier wallpapers kept for going back to the previous one
+pub const HISTORY_LENGTH: usize = 20;
+
+// Seconds a weather or location command may run before it is killed
+pub const COMMAND_TIMEOUT: u64 = 10;
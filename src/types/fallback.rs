@@ -0,0 +1,29 @@
+use crate::fmt;
+// Levels of the folder fallback chain, from most to least specific.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FallbackLevel {
+    Override,
+    Rule,
+    Weather,
+    WeatherParent,
+    WeatherFamily,
+    Daytime,
+    Default,
+    Root,
+}
+
+// fmt trait for logging and explain output
+impl fmt::Display for FallbackLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FallbackLevel::Override => write!(f, "override"),
+            FallbackLevel::Rule => write!(f, "weather rule"),
+            FallbackLevel::Weather => write!(f, "weather"),
+            FallbackLevel::WeatherParent => write!(f, "weather parent"),
+            FallbackLevel::WeatherFamily => write!(f, "weather family"),
+            FallbackLevel::Daytime => write!(f, "daytime"),
+            FallbackLevel::Default => write!(f, "default"),
+            FallbackLevel::Root => write!(f, "root"),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use crate::fmt;
+use anyhow::anyhow;
+// Ways to combine forecast entries into a single weather type.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ForecastPolicy {
+    Worst,
+    Majority,
+    NextHour,
+}
+
+// fmt trait for logging
+impl fmt::Display for ForecastPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ForecastPolicy::Worst => write!(f, "worst"),
+            ForecastPolicy::Majority => write!(f, "majority"),
+            ForecastPolicy::NextHour => write!(f, "next-hour"),
+        }
+    }
+}
+
+impl ForecastPolicy {
+    // Get forecast policy from str
+    pub fn from_string(policy: &str) -> Result<ForecastPolicy, anyhow::Error> {
+        match policy.to_lowercase().as_str() {
+            "worst" => Ok(ForecastPolicy::Worst),
+            "majority" => Ok(ForecastPolicy::Majority),
+            "next-hour" => Ok(ForecastPolicy::NextHour),
+            _ => Err(anyhow!("not a known forecast policy: {}", policy)),
+        }
+    }
+}
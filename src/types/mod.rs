@@ -1,4 +1,9 @@
+pub mod backend;
 pub mod daytime;
 pub mod default_types;
+pub mod fallback;
+pub mod forecastpolicy;
 pub mod modes;
+pub mod orientation;
+pub mod position;
 pub mod weathertype;
@@ -5,6 +5,7 @@ pub enum Mode {
     Cycle,
     Daytime,
     Weather,
+    Blend,
 }
 
 impl Mode {
@@ -14,6 +15,7 @@ impl Mode {
             "cycle-mode" => Ok(Mode::Cycle),
             "daytime-mode" => Ok(Mode::Daytime),
             "weather-mode" => Ok(Mode::Weather),
+            "blend-mode" => Ok(Mode::Blend),
             _ => Err(anyhow!("unknown mode")),
         }
     }
@@ -23,6 +25,7 @@ impl Mode {
             "cycle-mode".to_string(),
             "daytime-mode".to_string(),
             "weather-mode".to_string(),
+            "blend-mode".to_string(),
         ]
     }
 }
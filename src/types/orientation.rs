@@ -0,0 +1,41 @@
+use crate::fmt;
+use anyhow::anyhow;
+// Image orientations, used to filter candidate wallpapers.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Orientation {
+    Landscape,
+    Portrait,
+    Square,
+}
+
+// fmt trait for explain output
+impl fmt::Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Orientation::Landscape => write!(f, "landscape"),
+            Orientation::Portrait => write!(f, "portrait"),
+            Orientation::Square => write!(f, "square"),
+        }
+    }
+}
+
+impl Orientation {
+    // Get orientation from str
+    pub fn from_string(orientation: &str) -> Result<Orientation, anyhow::Error> {
+        match orientation.to_lowercase().as_str() {
+            "landscape" => Ok(Orientation::Landscape),
+            "portrait" => Ok(Orientation::Portrait),
+            "square" => Ok(Orientation::Square),
+            _ => Err(anyhow!("not a known aspect: {}", orientation)),
+        }
+    }
+
+    // Get orientation from image dimensions
+    pub fn from_dimensions(width: u32, height: u32) -> Orientation {
+        match width.cmp(&height) {
+            std::cmp::Ordering::Greater => Orientation::Landscape,
+            std::cmp::Ordering::Less => Orientation::Portrait,
+            std::cmp::Ordering::Equal => Orientation::Square,
+        }
+    }
+}
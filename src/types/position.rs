@@ -0,0 +1,38 @@
+use crate::fmt;
+use anyhow::anyhow;
+// Corners and centre of the screen, used to place the text overlay.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Position {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+// fmt trait for logging
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Position::TopLeft => write!(f, "top-left"),
+            Position::TopRight => write!(f, "top-right"),
+            Position::BottomLeft => write!(f, "bottom-left"),
+            Position::BottomRight => write!(f, "bottom-right"),
+            Position::Center => write!(f, "center"),
+        }
+    }
+}
+
+impl Position {
+    // Get position from str
+    pub fn from_string(position: &str) -> Result<Position, anyhow::Error> {
+        match position.to_lowercase().as_str() {
+            "top-left" => Ok(Position::TopLeft),
+            "top-right" => Ok(Position::TopRight),
+            "bottom-left" => Ok(Position::BottomLeft),
+            "bottom-right" => Ok(Position::BottomRight),
+            "center" | "centre" => Ok(Position::Center),
+            _ => Err(anyhow!("not a known position: {}", position)),
+        }
+    }
+}
@@ -1,19 +1,40 @@
 use crate::fmt;
 use anyhow::anyhow;
 
-// WeatherTypes
-#[derive(Debug, PartialEq, PartialOrd, Clone)]
+// WeatherTypes, covering the OpenWeatherMap condition codes. Variants with an intensity
+// or a specific kind of precipitation belong to one of the base types, see family.
+#[derive(Debug, PartialEq, PartialOrd, Clone, Default)]
 pub enum WeatherType {
+    #[default]
     Clear,
     FewClouds,
     ScatteredClouds,
     BrokenClouds,
     OvercastClouds,
     Drizzle,
+    LightDrizzle,
+    HeavyDrizzle,
     Mist,
+    Smoke,
+    Haze,
+    Dust,
+    Fog,
+    Sand,
+    Ash,
+    Squalls,
+    Tornado,
     Rain,
+    LightRain,
+    HeavyRain,
+    FreezingRain,
+    ShowerRain,
     Snow,
+    LightSnow,
+    HeavySnow,
+    Sleet,
+    ShowerSnow,
     Thunder,
+    HeavyThunder,
 }
 
 // fmt trait for creating path
@@ -26,10 +47,29 @@ impl fmt::Display for WeatherType {
             WeatherType::FewClouds => write!(f, "few clouds"),
             WeatherType::OvercastClouds => write!(f, "overcast clouds"),
             WeatherType::Drizzle => write!(f, "drizzle"),
+            WeatherType::LightDrizzle => write!(f, "light drizzle"),
+            WeatherType::HeavyDrizzle => write!(f, "heavy drizzle"),
             WeatherType::Mist => write!(f, "mist"),
+            WeatherType::Smoke => write!(f, "smoke"),
+            WeatherType::Haze => write!(f, "haze"),
+            WeatherType::Dust => write!(f, "dust"),
+            WeatherType::Fog => write!(f, "fog"),
+            WeatherType::Sand => write!(f, "sand"),
+            WeatherType::Ash => write!(f, "ash"),
+            WeatherType::Squalls => write!(f, "squalls"),
+            WeatherType::Tornado => write!(f, "tornado"),
             WeatherType::Rain => write!(f, "rain"),
+            WeatherType::LightRain => write!(f, "light rain"),
+            WeatherType::HeavyRain => write!(f, "heavy rain"),
+            WeatherType::FreezingRain => write!(f, "freezing rain"),
+            WeatherType::ShowerRain => write!(f, "shower rain"),
             WeatherType::Snow => write!(f, "snow"),
+            WeatherType::LightSnow => write!(f, "light snow"),
+            WeatherType::HeavySnow => write!(f, "heavy snow"),
+            WeatherType::Sleet => write!(f, "sleet"),
+            WeatherType::ShowerSnow => write!(f, "shower snow"),
             WeatherType::Thunder => write!(f, "thunder"),
+            WeatherType::HeavyThunder => write!(f, "heavy thunder"),
         }
     }
 }
@@ -45,27 +85,198 @@ impl WeatherType {
             "broken-clouds" => Ok(WeatherType::BrokenClouds),
             "overcast-clouds" => Ok(WeatherType::OvercastClouds),
             "drizzle" => Ok(WeatherType::Drizzle),
+            "light-drizzle" => Ok(WeatherType::LightDrizzle),
+            "heavy-drizzle" => Ok(WeatherType::HeavyDrizzle),
             "mist" => Ok(WeatherType::Mist),
+            "smoke" => Ok(WeatherType::Smoke),
+            "haze" => Ok(WeatherType::Haze),
+            "dust" => Ok(WeatherType::Dust),
+            "fog" => Ok(WeatherType::Fog),
+            "sand" => Ok(WeatherType::Sand),
+            "ash" => Ok(WeatherType::Ash),
+            "squalls" => Ok(WeatherType::Squalls),
+            "tornado" => Ok(WeatherType::Tornado),
             "rain" => Ok(WeatherType::Rain),
+            "light-rain" => Ok(WeatherType::LightRain),
+            "heavy-rain" => Ok(WeatherType::HeavyRain),
+            "freezing-rain" => Ok(WeatherType::FreezingRain),
+            "shower-rain" => Ok(WeatherType::ShowerRain),
             "snow" => Ok(WeatherType::Snow),
+            "light-snow" => Ok(WeatherType::LightSnow),
+            "heavy-snow" => Ok(WeatherType::HeavySnow),
+            "sleet" => Ok(WeatherType::Sleet),
+            "shower-snow" => Ok(WeatherType::ShowerSnow),
             "thunder" => Ok(WeatherType::Thunder),
+            "heavy-thunder" => Ok(WeatherType::HeavyThunder),
             _ => Err(anyhow!("not a known weathertype: {}", weathertype)),
         }
     }
 
-    // Get weather condition from weather id
+    // Get weather condition from weather id, see
+    // https://openweathermap.org/weather-conditions
     pub fn condition(id: i32) -> WeatherType {
         match id {
-            199..233 => WeatherType::Thunder,
-            299..321 => WeatherType::Drizzle,
-            499..532 => WeatherType::Rain,
-            599..623 => WeatherType::Snow,
-            700..781 => WeatherType::Mist,
+            // Thunderstorms with light or no rain and drizzle stay plain thunder.
+            200 | 201 | 210 | 211 | 230..=232 => WeatherType::Thunder,
+            202 | 212 | 221 => WeatherType::HeavyThunder,
+            300 | 310 => WeatherType::LightDrizzle,
+            302 | 312 | 314 => WeatherType::HeavyDrizzle,
+            500 => WeatherType::LightRain,
+            501 => WeatherType::Rain,
+            502..=504 => WeatherType::HeavyRain,
+            511 => WeatherType::FreezingRain,
+            520..=531 => WeatherType::ShowerRain,
+            600 => WeatherType::LightSnow,
+            601 => WeatherType::Snow,
+            602 => WeatherType::HeavySnow,
+            611..=616 => WeatherType::Sleet,
+            620..=622 => WeatherType::ShowerSnow,
+            701 => WeatherType::Mist,
+            711 => WeatherType::Smoke,
+            721 => WeatherType::Haze,
+            731 | 761 => WeatherType::Dust,
+            741 => WeatherType::Fog,
+            751 => WeatherType::Sand,
+            762 => WeatherType::Ash,
+            771 => WeatherType::Squalls,
+            781 => WeatherType::Tornado,
+            800 => WeatherType::Clear,
             801 => WeatherType::FewClouds,
             802 => WeatherType::ScatteredClouds,
             803 => WeatherType::BrokenClouds,
             804 => WeatherType::OvercastClouds,
-            _ => WeatherType::Clear,
+            // Remaining codes fall back to the type of their group.
+            _ => match id / 100 {
+                2 => WeatherType::Thunder,
+                3 => WeatherType::Drizzle,
+                5 => WeatherType::Rain,
+                6 => WeatherType::Snow,
+                7 => WeatherType::Mist,
+                _ => WeatherType::Clear,
+            },
         }
     }
+
+    // Base type of this weather type, one of the types available before intensities were
+    // added. Weather groups and folders named after the base type also match its members.
+    pub fn family(&self) -> WeatherType {
+        match self {
+            WeatherType::LightDrizzle | WeatherType::HeavyDrizzle => WeatherType::Drizzle,
+            WeatherType::Smoke
+            | WeatherType::Haze
+            | WeatherType::Dust
+            | WeatherType::Fog
+            | WeatherType::Sand
+            | WeatherType::Ash
+            | WeatherType::Squalls
+            | WeatherType::Tornado => WeatherType::Mist,
+            WeatherType::LightRain
+            | WeatherType::HeavyRain
+            | WeatherType::FreezingRain
+            | WeatherType::ShowerRain => WeatherType::Rain,
+            WeatherType::LightSnow
+            | WeatherType::HeavySnow
+            | WeatherType::Sleet
+            | WeatherType::ShowerSnow => WeatherType::Snow,
+            WeatherType::HeavyThunder => WeatherType::Thunder,
+            weather => weather.clone(),
+        }
+    }
+
+    // How severe the weather is, used to pick the worst weather of a forecast.
+    pub fn severity(&self) -> u8 {
+        match self {
+            WeatherType::Clear => 0,
+            WeatherType::FewClouds => 1,
+            WeatherType::ScatteredClouds => 2,
+            WeatherType::BrokenClouds => 3,
+            WeatherType::OvercastClouds => 4,
+            WeatherType::Mist | WeatherType::Haze => 5,
+            WeatherType::Smoke
+            | WeatherType::Dust
+            | WeatherType::Sand
+            | WeatherType::Ash
+            | WeatherType::Fog => 6,
+            WeatherType::LightDrizzle | WeatherType::Drizzle => 7,
+            WeatherType::HeavyDrizzle | WeatherType::LightRain => 8,
+            WeatherType::Rain | WeatherType::ShowerRain | WeatherType::LightSnow => 9,
+            WeatherType::HeavyRain
+            | WeatherType::Snow
+            | WeatherType::ShowerSnow
+            | WeatherType::Sleet => 10,
+            WeatherType::FreezingRain | WeatherType::HeavySnow => 11,
+            WeatherType::Thunder | WeatherType::Squalls => 12,
+            WeatherType::HeavyThunder => 13,
+            WeatherType::Tornado => 14,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_condition() {
+        let cases = [
+            (200, WeatherType::Thunder),
+            (212, WeatherType::HeavyThunder),
+            (300, WeatherType::LightDrizzle),
+            (321, WeatherType::Drizzle),
+            (500, WeatherType::LightRain),
+            (503, WeatherType::HeavyRain),
+            (511, WeatherType::FreezingRain),
+            (531, WeatherType::ShowerRain),
+            (613, WeatherType::Sleet),
+            (622, WeatherType::ShowerSnow),
+            (741, WeatherType::Fog),
+            (761, WeatherType::Dust),
+            (771, WeatherType::Squalls),
+            (781, WeatherType::Tornado),
+            (800, WeatherType::Clear),
+            (804, WeatherType::OvercastClouds),
+            (799, WeatherType::Mist),
+        ];
+        for (id, weather) in cases {
+            assert_eq!(WeatherType::condition(id), weather, "code {}", id);
+        }
+    }
+
+    #[test]
+    fn test_thunderstorm_conditions() {
+        // Every thunderstorm code listed by OpenWeatherMap.
+        let cases = [
+            (200, WeatherType::Thunder),
+            (201, WeatherType::Thunder),
+            (202, WeatherType::HeavyThunder),
+            (210, WeatherType::Thunder),
+            (211, WeatherType::Thunder),
+            (212, WeatherType::HeavyThunder),
+            (221, WeatherType::HeavyThunder),
+            (230, WeatherType::Thunder),
+            (231, WeatherType::Thunder),
+            (232, WeatherType::Thunder),
+        ];
+        for (id, weather) in cases {
+            assert_eq!(WeatherType::condition(id), weather, "code {}", id);
+        }
+    }
+
+    #[test]
+    fn test_parse_display_and_family() {
+        for id in (200..=232).chain(300..=321).chain(500..=804) {
+            let weather = WeatherType::condition(id);
+            let parsed = WeatherType::get_weathertype(&weather.to_string().replace(' ', "-"));
+            assert_eq!(parsed.unwrap(), weather);
+            // Families are closed, the family of a base type is itself.
+            assert_eq!(weather.family().family(), weather.family());
+        }
+        assert_eq!(WeatherType::HeavyRain.family(), WeatherType::Rain);
+        assert_eq!(WeatherType::Sleet.family(), WeatherType::Snow);
+        assert_eq!(WeatherType::Fog.family(), WeatherType::Mist);
+        assert_eq!(
+            WeatherType::BrokenClouds.family(),
+            WeatherType::BrokenClouds
+        );
+    }
 }
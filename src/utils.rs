@@ -1,6 +1,13 @@
 use crate::types::daytime::Daytime;
-use std::time::SystemTime;
-use std::time::UNIX_EPOCH;
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::hash::{BuildHasher, RandomState};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::LazyLock;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 // Use sunset and sunrise data to find the current day time. If sunset mode is on, function will
 // detect sunrise and sunset if current time is within configured sunset time limit.
@@ -36,6 +43,205 @@ pub fn fetch_daytime(sunrise: i32, sunset: i32, golden_hour: bool, sunset_limit:
     }
 }
 
+// Timezone boundaries take a while to load, they are loaded on first use and kept.
+static TIMEZONE_FINDER: LazyLock<tzf_rs::DefaultFinder> = LazyLock::new(tzf_rs::DefaultFinder::new);
+
+// IANA timezone name for a position, e.g. Europe/Amsterdam.
+pub fn fetch_timezone(lat: f64, lon: f64) -> String {
+    TIMEZONE_FINDER.get_tz_name(lon, lat).to_string()
+}
+
+// Offset from UTC in seconds of a timezone at a unix timestamp, None if the system
+// timezone database doesn't know the zone.
+pub fn fetch_utc_offset(timezone: &str, timestamp: i64) -> Option<i64> {
+    let timestamp = jiff::Timestamp::from_second(timestamp).ok()?;
+    let zone = jiff::tz::TimeZone::get(timezone).ok()?;
+    Some(zone.to_offset(timestamp).seconds() as i64)
+}
+
+// Solar elevation in degrees for a unix timestamp and location, accurate to about a degree.
+pub fn solar_elevation(timestamp: i64, lat: f64, lon: f64) -> f64 {
+    // Days since J2000
+    let days = timestamp as f64 / 86400.0 - 10957.5;
+    let mean_longitude = 280.460 + 0.9856474 * days;
+    let mean_anomaly = (357.528 + 0.9856003 * days).to_radians();
+    let ecliptic_longitude =
+        (mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin())
+            .to_radians();
+    let obliquity = (23.439 - 0.0000004 * days).to_radians();
+    let right_ascension =
+        (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+    let sidereal_degrees = (18.697374558 + 24.06570982441908 * days) * 15.0 + lon;
+    let hour_angle = sidereal_degrees.to_radians() - right_ascension;
+    let lat = lat.to_radians();
+    (lat.sin() * declination.sin() + lat.cos() * declination.cos() * hour_angle.cos())
+        .asin()
+        .to_degrees()
+}
+
+// Sunrise and sunset of the day around a unix timestamp, where the solar elevation crosses
+// -0.833 degrees. None during polar day and polar night.
+pub fn fetch_sun_times(timestamp: i64, lat: f64, lon: f64) -> Option<(i64, i64)> {
+    // Local solar midnight before the timestamp.
+    let offset = (lon / 15.0 * 3600.0) as i64;
+    let midnight = (timestamp + offset).div_euclid(86400) * 86400 - offset;
+    let up = |time: i64| solar_elevation(time, lat, lon) > -0.833;
+    let mut minutes = (1..=1440).map(|minute| midnight + minute * 60);
+    let sunrise = minutes.find(|time| up(*time) && !up(time - 60))?;
+    let sunset = minutes.find(|time| !up(*time) && up(time - 60))?;
+    Some((sunrise, sunset))
+}
+
+// Weight between night (0.0) and day (1.0). With known coordinates the weight follows the
+// solar elevation between -twilight and +twilight degrees, otherwise it ramps up and down
+// around sunrise and sunset over the golden hour time.
+pub fn fetch_day_weight(
+    now: i64,
+    sunrise: i64,
+    sunset: i64,
+    coordinates: Option<(f64, f64)>,
+    twilight: f64,
+    sunset_limit: i32,
+) -> f64 {
+    match coordinates {
+        Some((lat, lon)) => {
+            let elevation = solar_elevation(now, lat, lon);
+            ((elevation + twilight) / (2.0 * twilight.max(0.1))).clamp(0.0, 1.0)
+        }
+        None => {
+            let ramp = (sunset_limit.max(1) * 60) as f64;
+            let morning = ((now - sunrise) as f64 / ramp + 1.0) / 2.0;
+            let evening = ((sunset - now) as f64 / ramp + 1.0) / 2.0;
+            morning.min(evening).clamp(0.0, 1.0)
+        }
+    }
+}
+
+// Cache folder for fehther, $XDG_CACHE_HOME/fehther or ~/.cache/fehther.
+pub fn fetch_cache_dir() -> PathBuf {
+    fetch_xdg_dir("XDG_CACHE_HOME", &[".cache"])
+}
+
+// Data folder for fehther, $XDG_DATA_HOME/fehther or ~/.local/share/fehther.
+pub fn fetch_data_dir() -> PathBuf {
+    fetch_xdg_dir("XDG_DATA_HOME", &[".local", "share"])
+}
+
+// Runtime folder for fehther, $XDG_RUNTIME_DIR/fehther or the cache folder without it.
+pub fn fetch_runtime_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(dir).join("fehther"))
+        .unwrap_or_else(fetch_cache_dir)
+}
+
+// Resolve an XDG base directory, falling back to a folder in the home directory.
+fn fetch_xdg_dir(variable: &str, home_fallback: &[&str]) -> PathBuf {
+    std::env::var_os(variable)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            home::home_dir().map(|home| home.join(home_fallback.iter().collect::<PathBuf>()))
+        })
+        .unwrap_or_else(std::env::temp_dir)
+        .join("fehther")
+}
+
+// Parse an RFC 3339 timestamp like 2025-05-12T03:52:54.123+00:00 to seconds since epoch.
+pub fn parse_timestamp(text: &str) -> Option<i64> {
+    text.trim()
+        .parse::<jiff::Timestamp>()
+        .ok()
+        .map(|timestamp| timestamp.as_second())
+}
+
+// Current time in seconds since epoch.
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+// Random index below len, for picking wallpapers.
+pub fn random_index(len: usize) -> usize {
+    let random = RandomState::new().hash_one(SystemTime::now());
+    (random % len.max(1) as u64) as usize
+}
+
+// Modification time of a file in seconds since epoch, 0 if unknown.
+pub fn modified_secs(path: &Path) -> u64 {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs())
+}
+
+// Run a shell command and return what it printed. A command still running after the
+// timeout is killed, so a hanging script cannot stall the main loop.
+pub fn run_command(command: &str, timeout: Duration) -> Result<String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    // Read in the background, a full pipe would keep the command from exiting.
+    let mut stdout = child.stdout.take().ok_or_else(|| anyhow!("no output"))?;
+    let reader = thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() >= timeout {
+            child.kill().ok();
+            child.wait().ok();
+            return Err(anyhow!(
+                "{} killed after {} seconds",
+                command,
+                timeout.as_secs_f64()
+            ));
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    if !status.success() {
+        return Err(anyhow!("{} exited with {}", command, status));
+    }
+    reader
+        .join()
+        .map_err(|_| anyhow!("could not read output of {}", command))?
+        .map_err(Into::into)
+}
+
+// Resolutions of all connected outputs, read from xrandr. Empty if xrandr is unavailable.
+pub fn fetch_outputs() -> Vec<(u32, u32)> {
+    Command::new("xrandr")
+        .arg("--current")
+        .output()
+        .map(|output| parse_outputs(&String::from_utf8_lossy(&output.stdout)))
+        .unwrap_or_default()
+}
+
+// Parse lines like "HDMI-1 connected primary 1920x1080+0+0 ...".
+fn parse_outputs(xrandr: &str) -> Vec<(u32, u32)> {
+    xrandr
+        .lines()
+        .filter(|line| line.contains(" connected"))
+        .filter_map(|line| {
+            line.split_whitespace()
+                .find(|field| field.contains('x') && field.contains('+'))
+                .and_then(|geometry| geometry.split('+').next())
+                .and_then(|size| size.split_once('x'))
+                .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -75,6 +281,41 @@ mod tests {
             }
         }
     }
+    #[test]
+    fn test_solar_elevation() {
+        // March equinox 2024, 12:07 UTC, sun is almost straight above 0/0.
+        let equinox_noon = 1710936420;
+        assert!(solar_elevation(equinox_noon, 0.0, 0.0) > 88.0);
+        assert!(solar_elevation(equinox_noon + 12 * 3600, 0.0, 0.0) < -88.0);
+        // Amsterdam at solar noon (11:41 UTC) in June is about 61 degrees.
+        let june_noon = 1718794800 + 41 * 60;
+        let elevation = solar_elevation(june_noon, 52.37, 4.9);
+        assert!((elevation - 61.0).abs() < 1.5, "elevation {}", elevation);
+    }
+
+    #[test]
+    fn test_fetch_day_weight() {
+        let sunrise: i64 = 1747021974;
+        let sunset: i64 = 1747077771;
+        let weight = |now| fetch_day_weight(now, sunrise, sunset, None, 6.0, 30);
+        assert_eq!(weight(sunrise - 3600), 0.0);
+        assert_eq!(weight(sunrise), 0.5);
+        assert_eq!(weight(sunrise + 15 * 60), 0.75);
+        assert_eq!(weight(sunrise + 3600), 1.0);
+        assert_eq!(weight(sunset - 15 * 60), 0.75);
+        assert_eq!(weight(sunset + 3600), 0.0);
+    }
+
+    #[test]
+    fn test_parse_outputs() {
+        let xrandr = "Screen 0: minimum 320 x 200, current 4480 x 1440, maximum 16384 x 16384\n\
+                      DP-1 connected primary 2560x1440+0+0 (normal left inverted) 597mm x 336mm\n\
+                      HDMI-1 connected 1920x1080+2560+0 (normal left inverted) 527mm x 296mm\n\
+                      HDMI-2 disconnected (normal left inverted right x axis y axis)\n\
+                      DP-2 connected (normal left inverted right x axis y axis)";
+        assert_eq!(parse_outputs(xrandr), vec![(2560, 1440), (1920, 1080)]);
+    }
+
     #[test]
     fn test_fetch_daytime_mode_on() {
         // Sunrise and sunset times
@@ -122,6 +363,63 @@ mod tests {
         assert_eq!(result, Daytime::Night);
     }
 
+    #[test]
+    fn test_fetch_utc_offset() {
+        // 2025-01-15 and 2025-07-15, winter and summer time.
+        assert_eq!(fetch_utc_offset("Europe/Amsterdam", 1736942400), Some(3600));
+        assert_eq!(fetch_utc_offset("Europe/Amsterdam", 1752580800), Some(7200));
+        assert_eq!(fetch_utc_offset("UTC", 1752580800), Some(0));
+        assert_eq!(fetch_utc_offset("Nowhere/Atlantis", 1752580800), None);
+    }
+
+    #[test]
+    fn test_run_command() {
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            run_command("echo 52.37 4.89", timeout).unwrap(),
+            "52.37 4.89\n"
+        );
+        assert!(run_command("exit 3", timeout).is_err());
+        let started = Instant::now();
+        let error = run_command("sleep 10", Duration::from_millis(200)).unwrap_err();
+        assert!(error.to_string().contains("killed"), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(
+            parse_timestamp("2025-05-12T03:52:54.123+00:00"),
+            Some(1747021974)
+        );
+        assert_eq!(
+            parse_timestamp("2025-05-12T21:22:51+02:00"),
+            Some(1747077771)
+        );
+        // Offsets without a colon, as sent by some Home Assistant integrations.
+        assert_eq!(
+            parse_timestamp("2025-05-12T21:22:51+0200"),
+            Some(1747077771)
+        );
+        assert_eq!(
+            parse_timestamp("2025-05-12T19:22:51.999999Z"),
+            Some(1747077771)
+        );
+        assert_eq!(parse_timestamp("2024-02-29T00:00:00Z"), Some(1709164800));
+        assert_eq!(parse_timestamp("1969-12-31T23:59:00-00:00"), Some(-60));
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn test_fetch_sun_times() {
+        // Amsterdam on 12 May 2025, as reported by OpenWeatherMap.
+        let (sunrise, sunset) = fetch_sun_times(1747050000, 52.374, 4.8897).unwrap();
+        assert!((sunrise - 1747021974).abs() < 300, "{}", sunrise);
+        assert!((sunset - 1747077771).abs() < 300, "{}", sunset);
+        // Polar night in Longyearbyen.
+        assert_eq!(fetch_sun_times(1734530000, 78.2232, 15.6356), None);
+    }
+
     #[test]
     fn test_fetch_daytime_edge_cases() {
         let sunrise: i64 = 1747021974;
@@ -0,0 +1,287 @@
+use crate::error::FehtherError;
+use crate::fetch_weather::Observation;
+use crate::secret::ApiKey;
+use crate::types::default_types::COMMAND_TIMEOUT;
+use crate::types::weathertype::WeatherType;
+use crate::utils;
+use anyhow::{Context, Result, anyhow};
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Where the current weather comes from. Local sources need no API key, for example a
+// home weather station.
+#[derive(Debug)]
+pub enum WeatherSource {
+    OpenWeatherMap,
+    // JSON file kept up to date by another tool.
+    File(PathBuf),
+    // Command printing a condition or a JSON reading.
+    Command(String),
+    Mqtt(MqttSource),
+    // Weather entity of the [home-assistant] instance.
+    HomeAssistant,
+}
+
+impl WeatherSource {
+    // Read the current weather from a local source.
+    pub fn read(&mut self) -> Result<Observation> {
+        match self {
+            WeatherSource::OpenWeatherMap | WeatherSource::HomeAssistant => {
+                Err(anyhow!("{:?} is not a local source", self))
+            }
+            WeatherSource::File(path) => {
+                let text = fs::read_to_string(&*path)
+                    .with_context(|| FehtherError::Filesystem(path.clone()))?;
+                parse_reading(&text)
+                    .with_context(|| FehtherError::Parse(path.display().to_string()))
+            }
+            WeatherSource::Command(command) => {
+                let output = utils::run_command(command, Duration::from_secs(COMMAND_TIMEOUT))?;
+                parse_reading(&output).with_context(|| FehtherError::Parse(command.clone()))
+            }
+            WeatherSource::Mqtt(source) => source.read(),
+        }
+    }
+}
+
+// Reading of a local source, values are named like the weather rule fields.
+#[derive(Deserialize, Debug)]
+struct Reading {
+    // Condition name like "light-rain", or an OpenWeatherMap condition code.
+    #[serde(default)]
+    condition: Option<serde_json::Value>,
+    #[serde(default)]
+    description: String,
+    temp: Option<f64>,
+    feels_like: Option<f64>,
+    humidity: Option<f64>,
+    pressure: Option<f64>,
+    wind_speed: Option<f64>,
+    wind_gust: Option<f64>,
+    clouds: Option<f64>,
+    visibility: Option<f64>,
+    #[serde(default)]
+    rain: f64,
+    #[serde(default)]
+    snow: f64,
+    #[serde(default)]
+    location: String,
+}
+
+// Map a condition name, with dashes or spaces, or an OpenWeatherMap condition code.
+pub fn parse_condition(condition: &str) -> Result<WeatherType> {
+    let condition = condition.trim();
+    match condition.parse::<i32>() {
+        Ok(id) => Ok(WeatherType::condition(id)),
+        Err(_) => WeatherType::get_weathertype(&condition.replace(' ', "-")),
+    }
+}
+
+// Parse a reading, either a JSON object or just a condition.
+pub fn parse_reading(text: &str) -> Result<Observation> {
+    let text = text.trim();
+    if !text.starts_with('{') {
+        return Ok(Observation {
+            weather: Some(parse_condition(text)?),
+            ..Default::default()
+        });
+    }
+    let reading: Reading = serde_json::from_str(text)?;
+    let weather = match reading.condition {
+        Some(serde_json::Value::String(name)) => Some(parse_condition(&name)?),
+        Some(serde_json::Value::Number(id)) => {
+            id.as_i64().map(|id| WeatherType::condition(id as i32))
+        }
+        Some(serde_json::Value::Null) | None => None,
+        Some(other) => return Err(anyhow!("Invalid condition: {}", other)),
+    };
+    Ok(Observation {
+        weather,
+        description: reading.description,
+        temperature: reading.temp,
+        feels_like: reading.feels_like,
+        humidity: reading.humidity,
+        pressure: reading.pressure,
+        wind_speed: reading.wind_speed,
+        wind_gust: reading.wind_gust,
+        clouds: reading.clouds,
+        visibility: reading.visibility,
+        rain: reading.rain,
+        snow: reading.snow,
+        location: reading.location,
+        ..Default::default()
+    })
+}
+
+// MQTT broker and topic, parsed from the [weather] section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttSettings {
+    pub host: String,
+    pub port: u16,
+    pub topic: String,
+    pub username: Option<String>,
+    pub password: Option<ApiKey>,
+}
+
+// Last message on an MQTT topic. The connection is made on the first read and kept up in
+// a background thread, which reconnects when the broker goes away.
+#[derive(Debug)]
+pub struct MqttSource {
+    pub settings: MqttSettings,
+    // Latest payload, or the connection error since the last payload.
+    latest: Option<Arc<Mutex<Result<String, String>>>>,
+}
+
+impl MqttSource {
+    pub fn new(settings: MqttSettings) -> MqttSource {
+        MqttSource {
+            settings,
+            latest: None,
+        }
+    }
+
+    fn connect(&self) -> Arc<Mutex<Result<String, String>>> {
+        let settings = &self.settings;
+        let mut options = MqttOptions::new(
+            format!("fehther-{}", std::process::id()),
+            &settings.host,
+            settings.port,
+        );
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some(username) = &settings.username {
+            let password = settings.password.as_ref().map_or("", ApiKey::expose);
+            options.set_credentials(username, password);
+        }
+        let latest = Arc::new(Mutex::new(Err(format!(
+            "no message on {} yet",
+            settings.topic
+        ))));
+        let shared = latest.clone();
+        let topic = settings.topic.clone();
+        thread::spawn(move || {
+            let (client, mut connection) = Client::new(options, 10);
+            for notification in connection.iter() {
+                match notification {
+                    // Subscribe again after every reconnect, retained messages arrive right away.
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        client.subscribe(&topic, QoS::AtLeastOnce).ok();
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        *shared.lock().unwrap() =
+                            Ok(String::from_utf8_lossy(&publish.payload).to_string());
+                    }
+                    Ok(_) => {}
+                    Err(error) => {
+                        *shared.lock().unwrap() = Err(error.to_string());
+                        thread::sleep(Duration::from_secs(5));
+                    }
+                }
+            }
+        });
+        latest
+    }
+
+    fn read(&mut self) -> Result<Observation> {
+        if self.latest.is_none() {
+            self.latest = Some(self.connect());
+        }
+        let latest = self.latest.as_ref().unwrap().lock().unwrap().clone();
+        let broker = format!("{}:{}", self.settings.host, self.settings.port);
+        let payload = latest
+            .map_err(|error| anyhow!(error))
+            .with_context(|| FehtherError::Network(broker))?;
+        parse_reading(&payload).with_context(|| FehtherError::Parse(self.settings.topic.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reading() {
+        assert_eq!(
+            parse_reading("light rain\n").unwrap().weather,
+            Some(WeatherType::LightRain)
+        );
+        assert_eq!(
+            parse_reading("502").unwrap().weather,
+            Some(WeatherType::HeavyRain)
+        );
+        let observation = parse_reading(
+            r#"{"condition": "overcast-clouds", "temp": 12.5, "humidity": 81, "rain": 0.2,
+                "wind_gust": 14.1, "location": "Garden"}"#,
+        )
+        .unwrap();
+        assert_eq!(observation.weather, Some(WeatherType::OvercastClouds));
+        assert_eq!(observation.temperature, Some(12.5));
+        assert_eq!(observation.humidity, Some(81.0));
+        assert_eq!(observation.wind_gust, Some(14.1));
+        assert_eq!(observation.wind_speed, None);
+        assert_eq!(observation.rain, 0.2);
+        assert_eq!(observation.location, "Garden");
+        let observation = parse_reading(r#"{"condition": 611, "temp": 0.5}"#).unwrap();
+        assert_eq!(observation.weather, Some(WeatherType::Sleet));
+        // Values without a condition keep the current weather.
+        assert_eq!(parse_reading(r#"{"temp": 3}"#).unwrap().weather, None);
+        assert!(parse_reading("sunny").is_err());
+        assert!(parse_reading(r#"{"condition": true}"#).is_err());
+        assert!(parse_reading(r#"{"temp": "warm"}"#).is_err());
+    }
+
+    #[test]
+    fn test_file_and_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("station.json");
+        fs::write(&path, r#"{"condition": "snow", "temp": -2}"#).unwrap();
+        let observation = WeatherSource::File(path.clone()).read().unwrap();
+        assert_eq!(observation.weather, Some(WeatherType::Snow));
+        assert_eq!(observation.temperature, Some(-2.0));
+        let error = WeatherSource::File(dir.path().join("missing.json"))
+            .read()
+            .unwrap_err();
+        assert!(matches!(
+            crate::error::classify(&error),
+            Some(FehtherError::Filesystem(_))
+        ));
+
+        let mut source = WeatherSource::Command("echo thunder".to_string());
+        assert_eq!(source.read().unwrap().weather, Some(WeatherType::Thunder));
+        assert!(WeatherSource::Command("exit 1".to_string()).read().is_err());
+    }
+
+    // Needs a broker on localhost:1883, e.g. `mosquitto`.
+    #[test]
+    #[ignore]
+    fn test_mqtt() {
+        let topic = format!("fehther/test/{}", std::process::id());
+        let mut source = WeatherSource::Mqtt(MqttSource::new(MqttSettings {
+            host: "localhost".to_string(),
+            port: 1883,
+            topic: topic.clone(),
+            username: None,
+            password: None,
+        }));
+        assert!(source.read().is_err());
+        let (client, mut connection) =
+            Client::new(MqttOptions::new("fehther-test", "localhost", 1883), 10);
+        client
+            .publish(&topic, QoS::AtLeastOnce, true, r#"{"condition": "fog"}"#)
+            .unwrap();
+        for notification in connection.iter() {
+            if let Ok(Event::Incoming(Packet::PubAck(_))) = notification {
+                break;
+            }
+        }
+        thread::sleep(Duration::from_secs(1));
+        assert_eq!(source.read().unwrap().weather, Some(WeatherType::Fog));
+        // Clear the retained message.
+        client.publish(&topic, QoS::AtLeastOnce, true, "").unwrap();
+        connection.iter().nth(2);
+    }
+}